@@ -0,0 +1,383 @@
+use miette::Diagnostic;
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+use thiserror::Error;
+
+/**
+ * The name of the project config file.
+ * It is searched in the current working directory and all its parent directories.
+ */
+pub const CONFIG_FILE_NAME: &str = "dev_uploader.toml";
+
+#[derive(Debug, Error, Diagnostic)]
+pub enum ConfigFileError {
+    #[error("Config file not found: {}", .path.display())]
+    NotFound { path: PathBuf },
+    #[error("Cannot read config file {}", .path.display())]
+    ReadError {
+        path: PathBuf,
+        #[source]
+        io_error: std::io::Error,
+    },
+    #[error("Invalid config file {}", .path.display())]
+    #[diagnostic(code(dev_uploader::config_file_invalid))]
+    ParseError {
+        path: PathBuf,
+        #[source]
+        toml_error: toml::de::Error,
+    },
+    #[error("Profile '{profile}' not found in {} (available profiles: {})", .path.display(), .available.join(", "))]
+    UnknownProfile {
+        path: PathBuf,
+        profile: String,
+        available: Vec<String>,
+    },
+    #[error("Profile '{profile}' was requested, but no {CONFIG_FILE_NAME} was found")]
+    #[diagnostic(help(
+        "Create a {CONFIG_FILE_NAME} in the project dir or pass its path via --config."
+    ))]
+    ProfileWithoutConfigFile { profile: String },
+}
+
+/**
+ * All values which can be set in the config file, either on top level or inside a [profile.<name>] table.
+ * Every value is optional, since it can also be provided via cli flag.
+ *
+ * Note: Secrets should not be committed into the config file.
+ * Use `password_env` or `passphrase_env` to read them from an environment variable instead.
+ */
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigValues {
+    pub host: Option<String>,
+    pub port: Option<u16>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /**
+     * Name of an environment variable which contains the password
+     */
+    pub password_env: Option<String>,
+    pub pubkey: Option<PathBuf>,
+    pub privkey: Option<PathBuf>,
+    pub passphrase: Option<String>,
+    /**
+     * Name of an environment variable which contains the passphrase for the private key
+     */
+    pub passphrase_env: Option<String>,
+    /**
+     * Authenticate with the identities of the running ssh-agent
+     */
+    pub agent: Option<bool>,
+    /**
+     * One of: strict, accept-new, off
+     */
+    pub host_key_checking: Option<String>,
+    /**
+     * Path to a (project local) known_hosts file, default: ~/.ssh/known_hosts
+     */
+    pub known_hosts: Option<PathBuf>,
+    pub host_key_fingerprint: Option<String>,
+    pub connections: Option<u8>,
+    pub largest_first: Option<bool>,
+    pub sync_deletes: Option<bool>,
+    pub prune_empty_dirs: Option<bool>,
+    pub protected_paths: Option<Vec<String>>,
+    pub atomic: Option<bool>,
+    pub skip_unchanged: Option<bool>,
+    /**
+     * In milliseconds, see --stable-wait
+     */
+    pub stable_wait: Option<u64>,
+    /**
+     * Output, see --quiet, --verbose and --plain
+     */
+    pub quiet: Option<bool>,
+    pub verbose: Option<bool>,
+    pub plain: Option<bool>,
+    /**
+     * "text" or "json", see --output and --output-file
+     */
+    pub output: Option<String>,
+    pub output_file: Option<PathBuf>,
+    pub manifest: Option<bool>,
+    pub dry_run: Option<bool>,
+    /**
+     * Timeouts and keepalive interval in seconds, 0 = disabled
+     */
+    pub connect_timeout: Option<u64>,
+    pub timeout: Option<u64>,
+    pub keepalive: Option<u64>,
+    pub ignore_path_includes: Option<Vec<String>>,
+    pub ignore_path_ends: Option<Vec<String>>,
+    /**
+     * gitignore-style globs, see --include and --exclude
+     */
+    pub include: Option<Vec<String>>,
+    pub exclude: Option<Vec<String>>,
+    pub respect_ignore_files: Option<bool>,
+    /**
+     * Durations of the watcher in milliseconds, see --debounce and --settle
+     */
+    pub debounce: Option<u64>,
+    pub settle: Option<u64>,
+    /**
+     * Relative to the source of each upload pair
+     */
+    pub settle_marker: Option<PathBuf>,
+    /**
+     * Upload pairs in the same format as the `--upload-pair` cli flag: <source>[:target]
+     */
+    pub upload_pairs: Option<Vec<String>>,
+    pub upload_initial: Option<bool>,
+    /**
+     * Path to an OpenSSH client config file, default: ~/.ssh/config
+     */
+    pub ssh_config: Option<PathBuf>,
+}
+
+impl ConfigValues {
+    /**
+     * Returns a new ConfigValues where every value which is set in `other` overrides the value of `self`
+     */
+    pub fn merge(self, other: ConfigValues) -> ConfigValues {
+        ConfigValues {
+            host: other.host.or(self.host),
+            port: other.port.or(self.port),
+            username: other.username.or(self.username),
+            password: other.password.or(self.password),
+            password_env: other.password_env.or(self.password_env),
+            pubkey: other.pubkey.or(self.pubkey),
+            privkey: other.privkey.or(self.privkey),
+            passphrase: other.passphrase.or(self.passphrase),
+            passphrase_env: other.passphrase_env.or(self.passphrase_env),
+            agent: other.agent.or(self.agent),
+            host_key_checking: other.host_key_checking.or(self.host_key_checking),
+            known_hosts: other.known_hosts.or(self.known_hosts),
+            host_key_fingerprint: other.host_key_fingerprint.or(self.host_key_fingerprint),
+            connections: other.connections.or(self.connections),
+            largest_first: other.largest_first.or(self.largest_first),
+            sync_deletes: other.sync_deletes.or(self.sync_deletes),
+            prune_empty_dirs: other.prune_empty_dirs.or(self.prune_empty_dirs),
+            protected_paths: other.protected_paths.or(self.protected_paths),
+            atomic: other.atomic.or(self.atomic),
+            skip_unchanged: other.skip_unchanged.or(self.skip_unchanged),
+            stable_wait: other.stable_wait.or(self.stable_wait),
+            quiet: other.quiet.or(self.quiet),
+            verbose: other.verbose.or(self.verbose),
+            plain: other.plain.or(self.plain),
+            output: other.output.or(self.output),
+            output_file: other.output_file.or(self.output_file),
+            manifest: other.manifest.or(self.manifest),
+            dry_run: other.dry_run.or(self.dry_run),
+            connect_timeout: other.connect_timeout.or(self.connect_timeout),
+            timeout: other.timeout.or(self.timeout),
+            keepalive: other.keepalive.or(self.keepalive),
+            ignore_path_includes: other.ignore_path_includes.or(self.ignore_path_includes),
+            ignore_path_ends: other.ignore_path_ends.or(self.ignore_path_ends),
+            include: other.include.or(self.include),
+            exclude: other.exclude.or(self.exclude),
+            respect_ignore_files: other.respect_ignore_files.or(self.respect_ignore_files),
+            debounce: other.debounce.or(self.debounce),
+            settle: other.settle.or(self.settle),
+            settle_marker: other.settle_marker.or(self.settle_marker),
+            upload_pairs: other.upload_pairs.or(self.upload_pairs),
+            upload_initial: other.upload_initial.or(self.upload_initial),
+            ssh_config: other.ssh_config.or(self.ssh_config),
+        }
+    }
+}
+
+/**
+ * A parsed config file, with the selected profile already applied.
+ */
+#[derive(Debug)]
+pub struct ConfigFile {
+    pub path: PathBuf,
+    pub values: ConfigValues,
+}
+
+impl ConfigFile {
+    /**
+     * The directory of the config file.
+     * Relative paths inside the config file are resolved against this directory.
+     */
+    pub fn base_dir(&self) -> &Path {
+        self.path.parent().unwrap_or(Path::new("."))
+    }
+
+    /**
+     * Reads and parses a config file and applies the given profile on top of the top level values
+     */
+    pub fn load(path: &Path, profile: Option<&str>) -> Result<ConfigFile, ConfigFileError> {
+        let content = std::fs::read_to_string(path).map_err(|e| ConfigFileError::ReadError {
+            path: path.to_path_buf(),
+            io_error: e,
+        })?;
+
+        let values = parse_config(&content, profile).map_err(|e| match e {
+            ParseConfigError::Toml(toml_error) => ConfigFileError::ParseError {
+                path: path.to_path_buf(),
+                toml_error,
+            },
+            ParseConfigError::UnknownProfile { profile, available } => {
+                ConfigFileError::UnknownProfile {
+                    path: path.to_path_buf(),
+                    profile,
+                    available,
+                }
+            }
+        })?;
+
+        Ok(ConfigFile {
+            path: path.to_path_buf(),
+            values,
+        })
+    }
+
+    /**
+     * Loads the config file for this run:
+     * - if `explicit_path` is given, this file must exist
+     * - otherwise the file is searched from the cwd upwards, see find_config_file()
+     *
+     * Returns Ok(None) if no config file is used.
+     */
+    pub fn discover(
+        explicit_path: Option<&Path>,
+        profile: Option<&str>,
+    ) -> Result<Option<ConfigFile>, ConfigFileError> {
+        let path = match explicit_path {
+            Some(path) => {
+                if !path.is_file() {
+                    return Err(ConfigFileError::NotFound {
+                        path: path.to_path_buf(),
+                    });
+                }
+                Some(path.to_path_buf())
+            }
+            None => std::env::current_dir()
+                .ok()
+                .and_then(|cwd| find_config_file(&cwd)),
+        };
+
+        match (path, profile) {
+            (Some(path), profile) => ConfigFile::load(&path, profile).map(Some),
+            (None, Some(profile)) => Err(ConfigFileError::ProfileWithoutConfigFile {
+                profile: profile.to_string(),
+            }),
+            (None, None) => Ok(None),
+        }
+    }
+}
+
+/**
+ * Walks up from start_dir and returns the first dev_uploader.toml found
+ */
+pub fn find_config_file(start_dir: &Path) -> Option<PathBuf> {
+    start_dir
+        .ancestors()
+        .map(|dir| dir.join(CONFIG_FILE_NAME))
+        .find(|candidate| candidate.is_file())
+}
+
+enum ParseConfigError {
+    Toml(toml::de::Error),
+    UnknownProfile {
+        profile: String,
+        available: Vec<String>,
+    },
+}
+
+/**
+ * Parses the content of a dev_uploader.toml file:
+ * top level values are the defaults, [profile.<name>] tables override them.
+ *
+ * Note: The profile table is split off manually (instead of using #[serde(flatten)]),
+ * since serde does not support deny_unknown_fields for flattened structs.
+ */
+fn parse_config(content: &str, profile: Option<&str>) -> Result<ConfigValues, ParseConfigError> {
+    let mut table: toml::Table = toml::from_str(content).map_err(ParseConfigError::Toml)?;
+
+    let mut profiles: HashMap<String, ConfigValues> = match table.remove("profile") {
+        Some(profiles) => profiles.try_into().map_err(ParseConfigError::Toml)?,
+        None => HashMap::new(),
+    };
+    let defaults: ConfigValues = table.try_into().map_err(ParseConfigError::Toml)?;
+
+    match profile {
+        None => Ok(defaults),
+        Some(profile) => match profiles.remove(profile) {
+            Some(profile_values) => Ok(defaults.merge(profile_values)),
+            None => {
+                let mut available = profiles.into_keys().collect::<Vec<_>>();
+                available.sort();
+                Err(ParseConfigError::UnknownProfile {
+                    profile: profile.to_string(),
+                    available,
+                })
+            }
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLE_CONFIG: &str = r#"
+        host = "localhost"
+        port = 2022
+        username = "playground"
+        upload_pairs = ["playground:."]
+        ignore_path_ends = [".gitignore"]
+
+        [profile.staging]
+        host = "staging.example.com"
+        port = 22
+        password_env = "STAGING_PASSWORD"
+
+        [profile.prod]
+        host = "prod.example.com"
+        upload_pairs = ["dist:www", "api:backend"]
+    "#;
+
+    #[test]
+    fn test_parse_config_without_profile() {
+        let values = parse_config(EXAMPLE_CONFIG, None).ok().unwrap();
+        assert_eq!(values.host.as_deref(), Some("localhost"));
+        assert_eq!(values.port, Some(2022));
+        assert_eq!(values.upload_pairs, Some(vec!["playground:.".to_string()]));
+        assert_eq!(values.password_env, None);
+    }
+
+    #[test]
+    fn test_parse_config_with_profile() {
+        let values = parse_config(EXAMPLE_CONFIG, Some("staging")).ok().unwrap();
+        assert_eq!(values.host.as_deref(), Some("staging.example.com"));
+        assert_eq!(values.port, Some(22));
+        // not overridden by the profile => taken from top level
+        assert_eq!(values.username.as_deref(), Some("playground"));
+        assert_eq!(values.password_env.as_deref(), Some("STAGING_PASSWORD"));
+
+        let values = parse_config(EXAMPLE_CONFIG, Some("prod")).ok().unwrap();
+        assert_eq!(values.upload_pairs.map(|p| p.len()), Some(2));
+    }
+
+    #[test]
+    fn test_parse_config_unknown_profile() {
+        match parse_config(EXAMPLE_CONFIG, Some("dev")) {
+            Err(ParseConfigError::UnknownProfile { available, .. }) => {
+                assert_eq!(available, vec!["prod", "staging"]);
+            }
+            _ => panic!("Expected UnknownProfile error"),
+        }
+    }
+
+    #[test]
+    fn test_parse_config_unknown_key() {
+        let result = parse_config("hostname = \"localhost\"", None);
+        assert!(matches!(result, Err(ParseConfigError::Toml(_))));
+    }
+}
@@ -1,5 +1,7 @@
 use std::path::PathBuf;
 
+pub mod config_file;
+pub mod settings;
 pub mod upload_pair;
 
 // use clap::builder::NumberParser;
@@ -16,14 +18,7 @@ pub fn setup_cli() -> Command {
                 .short('u')
                 .long("upload-pair")
                 .value_name("upload-pair")
-                // TODO: Allow ArgAction::Append to allow multiple upload pairs
-                // Read it in main.rs like this:
-                // let upload_pairs = matches
-                // .get_many::<String>("upload_pair")
-                // .unwrap_or_default()
-                // .map(|v| v.as_str())
-                // .collect::<Vec<_>>();
-                .action(ArgAction::Set)
+                .action(ArgAction::Append)
                 .help(
                     [
                         "An upload-pair in the format of <source>[:target].",
@@ -32,12 +27,12 @@ pub fn setup_cli() -> Command {
                         "          (only works with relative paths!).",
                         "- If the content of source should be uploaded to the cwd of the remote host, use '.' or './' as target.",
                         "  Example: '-u playground:.' will upload the content of the playground folder to the cwd of the remote host.",
-                        "- Currently only one upload-pair is supported and required.",
-                        "- Later: support for multiple upload-pairs should be added.",
+                        "- At least one upload-pair is required. Can be added multiple times.",
+                        "  Example: '-u dist:www -u api:backend' watches both folders and uploads them via the same connections.",
+                        "- Can also be set via 'upload_pairs' in the config file.",
                     ]
                     .join("\n"),
-                )
-                .required(true),
+                ),
         )
         .arg(Arg::new("host")
                 .short('H') // small h already used for help
@@ -45,9 +40,11 @@ pub fn setup_cli() -> Command {
                 .value_name("sftp-host")
                 .help([
                     "The sftp host to connect to.", 
-                    "Should be a valid hostname or IP address.",
+                    "Should be a valid hostname, IP address or a Host alias from the ssh config (~/.ssh/config).",
+                    "For an ssh config alias, HostName, User, Port and IdentityFile are used,",
+                    "unless they are given explicitly via flag or config file.",
+                    "Required, either via this flag or via 'host' in the config file.",
                 ].join("\n"))
-                .required(true)
         )
         .arg(Arg::new("port")
                 .short('P')
@@ -66,8 +63,8 @@ pub fn setup_cli() -> Command {
                 .value_name("sftp-username")
                 .help([
                     "The sftp username to use for the connection.", 
+                    "Required, either via this flag or via 'username' in the config file.",
                 ].join("\n"))
-                .required(true)
         )
         .arg(Arg::new("pubkey")
                 .short('k')
@@ -79,7 +76,6 @@ pub fn setup_cli() -> Command {
                 ].join("\n"))
                 .value_parser(value_parser!(PathBuf))
                 .conflicts_with("password")
-                .required(true)
         )
         .arg(Arg::new("privkey")
                 .short('K')
@@ -91,7 +87,6 @@ pub fn setup_cli() -> Command {
                 ].join("\n"))
                 .value_parser(value_parser!(PathBuf))
                 .conflicts_with("password")
-                .required(true)
         )
         .arg(Arg::new("passphrase")
                 .short('S')
@@ -111,8 +106,47 @@ pub fn setup_cli() -> Command {
                 "The sftp password to use for the connection.", 
                 "Should be a valid password.",
             ].join("\n"))
-            .required(true)
-            .conflicts_with_all(&["pubkey", "privkey"])
+            .conflicts_with_all(["pubkey", "privkey"])
+        )
+        .arg(Arg::new("agent")
+            .short('A')
+            .long("agent")
+            .help([
+                "Authenticate with the identities of the running ssh-agent.",
+                "This is the default when SSH_AUTH_SOCK is set and no password or key is given.",
+            ].join("\n"))
+            .action(ArgAction::SetTrue)
+            .conflicts_with_all(["password", "pubkey", "privkey"])
+        )
+        .arg(Arg::new("host_key_checking")
+            .long("host-key-checking")
+            .value_name("mode")
+            .value_parser(["strict", "accept-new", "off"])
+            .help([
+                "How to verify the host key of the server against the known_hosts file:",
+                "- strict: refuse unknown and changed host keys",
+                "- accept-new: add unknown host keys to known_hosts, refuse changed ones (trust on first use)",
+                "- off: do not verify the host key (NOT recommended)",
+            ].join("\n"))
+            .default_value("accept-new")
+        )
+        .arg(Arg::new("known_hosts")
+            .long("known-hosts")
+            .value_name("known-hosts-file")
+            .value_parser(value_parser!(PathBuf))
+            .help([
+                "Optional: Path to a known_hosts file, for example a project local one.",
+                "Default: ~/.ssh/known_hosts",
+            ].join("\n"))
+        )
+        .arg(Arg::new("host_key_fingerprint")
+            .long("host-key-fingerprint")
+            .value_name("fingerprint")
+            .help([
+                "Optional: Pin the host key of the server to this SHA256 fingerprint, like 'SHA256:abc...'.",
+                "Get it with 'ssh-keygen -lf <keyfile>' or 'ssh-keyscan <host> | ssh-keygen -lf -'.",
+                "If given, the known_hosts file is not used. Useful for CI.",
+            ].join("\n"))
         )
         .arg(
             Arg::new("connection_count")
@@ -124,6 +158,123 @@ pub fn setup_cli() -> Command {
                 .help("Number of connections to use for the sftp upload.")
                 .default_value("6")
         )
+        .arg(
+            Arg::new("sync_deletes")
+                .long("sync-deletes")
+                .action(ArgAction::SetTrue)
+                .help([
+                    "Remove the remote file when the local file is deleted.",
+                    "Locally deleted dirs are only removed on the remote if they are empty there.",
+                ].join("\n"))
+        )
+        .arg(
+            Arg::new("prune_empty_dirs")
+                .long("prune-empty-dirs")
+                .action(ArgAction::SetTrue)
+                .requires("sync_deletes")
+                .help("With --sync-deletes: also remove remote dirs which are empty after removing a file.")
+        )
+        .arg(
+            Arg::new("protected_paths")
+                .long("protect")
+                .value_name("path_pattern")
+                .action(ArgAction::Append)
+                .help([
+                    "Optional: Paths which are never removed on the remote by --sync-deletes.",
+                    "Relative to the upload pair, wildcards * and ? are allowed.",
+                    "Can be added multiple times.",
+                    "For example: '--protect uploads --protect *.env'",
+                ].join("\n"))
+        )
+        .arg(
+            Arg::new("largest_first")
+                .long("largest-first")
+                .action(ArgAction::SetTrue)
+                .help([
+                    "Upload the biggest files of a batch first.",
+                    "Default: files are uploaded in the order they were detected.",
+                ].join("\n"))
+        )
+        .arg(
+            Arg::new("atomic")
+                .long("atomic")
+                .action(ArgAction::SetTrue)
+                .help([
+                    "Upload each file into a hidden temp file next to the target and rename it over the target afterwards,",
+                    "so that the server never serves a half-written file.",
+                    "The rename is atomic on servers with the posix-rename@openssh.com extension (OpenSSH) or sftp protocol version 5+.",
+                    "On other servers, the old target is removed before the rename and is missing for a moment.",
+                    "Leftover temp files of crashed runs are removed on startup.",
+                ].join("\n"))
+        )
+        .arg(
+            Arg::new("skip_unchanged")
+                .long("skip-unchanged")
+                .action(ArgAction::SetTrue)
+                .help([
+                    "Skip files whose remote file has the same size and modification time.",
+                    "The modification time of uploaded files is set to the local one, so that the next run can skip them.",
+                    "Useful with --upload-initial.",
+                ].join("\n"))
+        )
+        .arg(
+            Arg::new("stable_wait")
+                .long("stable-wait")
+                .value_name("milliseconds")
+                .value_parser(value_parser!(u64))
+                .help([
+                    "Optional: Before uploading a file, wait until its size and modification time did not change for this duration,",
+                    "so that files which are still being written (e.g. zip artifacts or videos) are not uploaded truncated.",
+                    "A file which changes during its upload is queued again. 0 = disabled (default).",
+                ].join("\n"))
+        )
+        .arg(
+            Arg::new("manifest")
+                .long("manifest")
+                .action(ArgAction::SetTrue)
+                .help([
+                    "Remember the content hash of every uploaded file in .dev_uploader/state.json",
+                    "(next to the config file, or in the current dir) and skip files whose content did not change since their last upload.",
+                    "Changes on the remote by other processes are not detected, delete the file to force a full upload.",
+                ].join("\n"))
+        )
+        .arg(
+            Arg::new("dry_run")
+                .long("dry-run")
+                .action(ArgAction::SetTrue)
+                .help([
+                    "Connect and plan, but do not change anything on the remote.",
+                    "Prints every mkdir, upload, delete and rename which would be performed,",
+                    "for the initial upload, the push subcommand and all watch events.",
+                ].join("\n"))
+        )
+        .arg(
+            Arg::new("connect_timeout")
+                .long("connect-timeout")
+                .value_name("seconds")
+                .value_parser(value_parser!(u64))
+                .help("Timeout for establishing the TCP connection to the server, 0 = no timeout.")
+                .default_value("10")
+        )
+        .arg(
+            Arg::new("timeout")
+                .long("timeout")
+                .value_name("seconds")
+                .value_parser(value_parser!(u64))
+                .help([
+                    "Timeout for a single ssh/sftp operation (e.g. writing a chunk of a file), 0 = no timeout.",
+                    "A stalled transfer fails with a timeout error after this time and the connection is re-established.",
+                ].join("\n"))
+                .default_value("30")
+        )
+        .arg(
+            Arg::new("keepalive")
+                .long("keepalive")
+                .value_name("seconds")
+                .value_parser(value_parser!(u64))
+                .help("Interval for ssh keepalive messages, 0 = no keepalive.")
+                .default_value("15")
+        )
         .arg(
             Arg::new("watcher_ignore_path_includes")
                 .short('i')
@@ -151,6 +302,145 @@ pub fn setup_cli() -> Command {
                     "For example: '-e .js.map -e stats.js' will filter all file paths ending with '.js.map' or 'stats.js'.",
                 ].join("\n"))
         )
+        .arg(
+            Arg::new("include_globs")
+                .long("include")
+                .value_name("glob")
+                .action(ArgAction::Append)
+                .help([
+                    "Optional: gitignore-style glob of files to upload, all other files are ignored.",
+                    "Globs without a slash match at any depth, globs with a slash are relative to the upload pair source.",
+                    "Applies to watch events, --upload-initial and push. Can be added multiple times.",
+                    "For example: --include 'assets/**/*.{png,svg}' --include '*.js'",
+                ].join("\n"))
+        )
+        .arg(
+            Arg::new("exclude_globs")
+                .long("exclude")
+                .value_name("glob")
+                .action(ArgAction::Append)
+                .help([
+                    "Optional: gitignore-style glob of files to ignore, wins over --include.",
+                    "A leading '!' re-includes files of an earlier exclude. Can be added multiple times.",
+                    "For example: --exclude '**/*.map' --exclude 'cache/**'",
+                ].join("\n"))
+        )
+        .arg(
+            Arg::new("respect_ignore_files")
+                .long("respect-ignore-files")
+                .action(ArgAction::SetTrue)
+                .help([
+                    "Ignore the files matched by the .gitignore, .ignore and .devuploaderignore files",
+                    "inside the upload pair sources (in every sub dir, like git does). The .git dir is always ignored in this mode.",
+                    "Applies to watch events, --upload-initial and push.",
+                    "Ignore files of parent dirs of the source are not used, and ignore files are only read on startup.",
+                ].join("\n"))
+        )
+        .arg(
+            Arg::new("debounce")
+                .long("debounce")
+                .value_name("milliseconds")
+                .value_parser(value_parser!(u64))
+                .help("The watcher collects file events for this duration and uploads them as one batch.")
+                .default_value("1500")
+        )
+        .arg(
+            Arg::new("settle")
+                .long("settle")
+                .value_name("milliseconds")
+                .value_parser(value_parser!(u64))
+                .conflicts_with("settle_marker")
+                .help([
+                    "Optional: Wait until no file changed for this duration before uploading,",
+                    "so that a build which writes its files over several seconds is uploaded as one batch.",
+                    "For example: '--settle 3000'",
+                ].join("\n"))
+        )
+        .arg(
+            Arg::new("settle_marker")
+                .long("settle-marker")
+                .value_name("marker-file")
+                .value_parser(value_parser!(PathBuf))
+                .help([
+                    "Optional: Wait until this file is (re-)written before uploading the changed files,",
+                    "e.g. a file touched by the last step of the build. Relative to the source of each upload pair.",
+                    "The marker itself is not uploaded. For example: '--settle-marker .build-complete'",
+                ].join("\n"))
+        )
+        .arg(
+            Arg::new("ssh_config")
+                .long("ssh-config")
+                .value_name("ssh-config-file")
+                .value_parser(value_parser!(PathBuf))
+                .help([
+                    "Optional: Path to an OpenSSH client config file to resolve the --host alias with.",
+                    "Default: ~/.ssh/config (if it exists).",
+                ].join("\n"))
+        )
+        .arg(
+            Arg::new("config")
+                .long("config")
+                .value_name("config-file")
+                .value_parser(value_parser!(PathBuf))
+                .help([
+                    "Optional: Path to a dev_uploader.toml config file.",
+                    "If not given, the file 'dev_uploader.toml' is searched in the cwd and all of its parent directories.",
+                    "Flags given on the command line always override values from the config file.",
+                ].join("\n"))
+        )
+        .arg(
+            Arg::new("profile")
+                .long("profile")
+                .value_name("profile-name")
+                .help([
+                    "Optional: Name of a profile in the config file, like 'staging' for a [profile.staging] table.",
+                    "The values of the profile override the top level values of the config file.",
+                ].join("\n"))
+        )
+        .arg(
+            Arg::new("quiet")
+                .short('q')
+                .long("quiet")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("verbose")
+                .help("Only print errors and the result of each batch, without progressbars.")
+        )
+        .arg(
+            Arg::new("verbose")
+                .short('v')
+                .long("verbose")
+                .action(ArgAction::SetTrue)
+                .help("Print all settings on startup, every created remote dir and one line per uploaded file.")
+        )
+        .arg(
+            Arg::new("plain")
+                .long("plain")
+                .action(ArgAction::SetTrue)
+                .help([
+                    "Print one log line per uploaded file instead of progressbars.",
+                    "This is the default, if the output is not an interactive terminal (e.g. in CI or when piped into tee).",
+                ].join("\n"))
+        )
+        .arg(
+            Arg::new("output")
+                .long("output")
+                .value_name("format")
+                .value_parser(["text", "json"])
+                .default_value("text")
+                .help([
+                    "- text: human readable output",
+                    "- json: additionally write newline-delimited json events (batch_detected, file_started, file_uploaded,",
+                    "  file_skipped, file_failed, batch_finished) to stdout or --output-file.",
+                    "  On stdout, the text output is written to stderr instead.",
+                ].join("\n"))
+        )
+        .arg(
+            Arg::new("output_file")
+                .long("output-file")
+                .value_name("file")
+                .value_parser(value_parser!(PathBuf))
+                .help("Optional: Write the json events of --output json to this file (truncated on startup) instead of stdout.")
+        )
         .arg(
             Arg::new("upload_initial")
                 .short('I')
@@ -159,4 +449,13 @@ pub fn setup_cli() -> Command {
                 .action(clap::ArgAction::SetTrue)
                 .default_value("false")
         )
+        .subcommand(
+            Command::new("push")
+                .about([
+                    "Upload all files of the upload pairs once and exit, without watching.",
+                    "Prints a summary and exits with a non-zero code if any file failed to upload.",
+                ].join("\n"))
+        )
+        // all flags can be given before and after the push subcommand
+        .mut_args(|arg| arg.global(true))
 }
@@ -0,0 +1,555 @@
+use clap::{parser::ValueSource, ArgMatches};
+use std::{
+    fmt,
+    io::IsTerminal,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use super::{
+    config_file::{ConfigFile, ConfigValues},
+    upload_pair::UploadPair,
+};
+use crate::{
+    sftp::{
+        connection_options::ConnectionOptions,
+        host_key::{HostKeyMode, HostKeyPolicy},
+        ssh_config::{SshConfig, SshHostConfig},
+    },
+    uploader::{
+        progress_actor::Verbosity, upload_event::EventTarget, upload_manifest::UploadManifest,
+    },
+    watcher::settle::SettleMode,
+};
+
+/**
+ * The final settings for one dev_uploader run.
+ *
+ * Precedence (highest first):
+ * 1. cli flags
+ * 2. the selected [profile.<name>] in the config file
+ * 3. top level values in the config file
+ * 4. the matching Host blocks in the ssh config (~/.ssh/config)
+ * 5. the clap default values
+ */
+pub struct Settings {
+    pub config_file: Option<PathBuf>,
+    pub upload_pairs: Vec<UploadPair>,
+    /**
+     * The real host to connect to (after resolving a ssh config alias via HostName)
+     */
+    pub host: String,
+    /**
+     * The host as given via --host or config file, before resolving it via ssh config
+     */
+    pub host_alias: String,
+    pub port: u16,
+    pub username: String,
+    pub pubkey: Option<PathBuf>,
+    pub privkey: Option<PathBuf>,
+    pub passphrase: Option<String>,
+    pub password: Option<String>,
+    /**
+     * Authenticate via ssh-agent (set explicitly, or as default when SSH_AUTH_SOCK is set and no other credentials are given)
+     */
+    pub use_agent: bool,
+    pub host_key_policy: HostKeyPolicy,
+    pub connection_count: u8,
+    /**
+     * Upload the biggest files of a batch first
+     */
+    pub largest_first: bool,
+    /**
+     * Remove remote files when their local file is deleted
+     */
+    pub sync_deletes: bool,
+    pub prune_empty_dirs: bool,
+    pub protected_paths: Vec<String>,
+    /**
+     * Write uploads to a temp file and rename it over the target
+     */
+    pub atomic_uploads: bool,
+    /**
+     * Skip files with the same size and mtime on the remote
+     */
+    pub skip_unchanged: bool,
+    /**
+     * Wait until local files are stable for this interval before uploading them, None if disabled
+     */
+    pub stable_wait: Option<Duration>,
+    /**
+     * The upload manifest (content hashes of the uploaded files), None if disabled
+     */
+    pub manifest_file: Option<PathBuf>,
+    /**
+     * Only print the planned remote operations
+     */
+    pub dry_run: bool,
+    pub connection_options: ConnectionOptions,
+    pub ignore_includes: Vec<String>,
+    pub ignore_ends: Vec<String>,
+    pub include_globs: Vec<String>,
+    pub exclude_globs: Vec<String>,
+    pub respect_ignore_files: bool,
+    /**
+     * The watcher collects events for this duration before it sends a batch
+     */
+    pub debounce: Duration,
+    /**
+     * Holds back the batches until a build is finished, None = send every batch directly
+     */
+    pub settle_mode: Option<SettleMode>,
+    pub upload_initial: bool,
+    pub verbosity: Verbosity,
+    /**
+     * Draw progressbars, false => plain log lines (--plain, --quiet or no interactive terminal)
+     */
+    pub progress_bars: bool,
+    /**
+     * Where the json events are written to, None = text output only (see --output json)
+     */
+    pub json_events: Option<EventTarget>,
+}
+
+/**
+ * Used for the settings dump with --verbose, hides the passphrase and password.
+ * Destructures all fields on purpose, so that a new field cannot be left out of the dump.
+ */
+impl fmt::Debug for Settings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Settings {
+            config_file,
+            upload_pairs,
+            host,
+            host_alias,
+            port,
+            username,
+            pubkey,
+            privkey,
+            passphrase,
+            password,
+            use_agent,
+            host_key_policy,
+            connection_count,
+            largest_first,
+            sync_deletes,
+            prune_empty_dirs,
+            protected_paths,
+            atomic_uploads,
+            skip_unchanged,
+            stable_wait,
+            manifest_file,
+            dry_run,
+            connection_options,
+            ignore_includes,
+            ignore_ends,
+            include_globs,
+            exclude_globs,
+            respect_ignore_files,
+            debounce,
+            settle_mode,
+            upload_initial,
+            verbosity,
+            progress_bars,
+            json_events,
+        } = self;
+        let hidden = |secret: &Option<String>| secret.as_ref().map(|_| "******");
+
+        f.debug_struct("Settings")
+            .field("config_file", config_file)
+            .field("upload_pairs", upload_pairs)
+            .field("host", host)
+            .field("host_alias", host_alias)
+            .field("port", port)
+            .field("username", username)
+            .field("pubkey", pubkey)
+            .field("privkey", privkey)
+            .field("passphrase", &hidden(passphrase))
+            .field("password", &hidden(password))
+            .field("use_agent", use_agent)
+            .field("host_key_policy", host_key_policy)
+            .field("connection_count", connection_count)
+            .field("largest_first", largest_first)
+            .field("sync_deletes", sync_deletes)
+            .field("prune_empty_dirs", prune_empty_dirs)
+            .field("protected_paths", protected_paths)
+            .field("atomic_uploads", atomic_uploads)
+            .field("skip_unchanged", skip_unchanged)
+            .field("stable_wait", stable_wait)
+            .field("manifest_file", manifest_file)
+            .field("dry_run", dry_run)
+            .field("connection_options", connection_options)
+            .field("ignore_includes", ignore_includes)
+            .field("ignore_ends", ignore_ends)
+            .field("include_globs", include_globs)
+            .field("exclude_globs", exclude_globs)
+            .field("respect_ignore_files", respect_ignore_files)
+            .field("debounce", debounce)
+            .field("settle_mode", settle_mode)
+            .field("upload_initial", upload_initial)
+            .field("verbosity", verbosity)
+            .field("progress_bars", progress_bars)
+            .field("json_events", json_events)
+            .finish()
+    }
+}
+
+impl Settings {
+    pub fn resolve(matches: &ArgMatches, config: Option<ConfigFile>) -> Result<Self, String> {
+        let (config_path, config_base_dir, values) = match config {
+            Some(config) => (
+                Some(config.path.clone()),
+                config.base_dir().to_path_buf(),
+                config.values,
+            ),
+            None => (None, PathBuf::from("."), ConfigValues::default()),
+        };
+
+        // upload pairs from the cli are relative to the cwd, upload pairs from the config file relative to the config file
+        let upload_pairs = match cli_many(matches, "upload_pair") {
+            Some(pairs) => pairs
+                .iter()
+                .map(|p| UploadPair::from_uploadpair_string(p))
+                .collect(),
+            None => values
+                .upload_pairs
+                .unwrap_or_default()
+                .iter()
+                .map(|p| UploadPair::from_uploadpair_string_with_base(p, &config_base_dir))
+                .collect::<Vec<_>>(),
+        };
+        if upload_pairs.is_empty() {
+            return Err(String::from(
+                "At least one upload-pair must be provided (via --upload-pair or 'upload_pairs' in the config file)",
+            ));
+        }
+
+        let host_alias = cli_one::<String>(matches, "host")
+            .or(values.host)
+            .ok_or("The sftp host must be provided (via --host or 'host' in the config file)")?;
+
+        let ssh_config_path = cli_one::<PathBuf>(matches, "ssh_config").or(values
+            .ssh_config
+            .map(|p| resolve_config_path(&p, &config_base_dir)));
+        let ssh_host_config = read_ssh_host_config(ssh_config_path.as_deref(), &host_alias)?;
+
+        let host = ssh_host_config
+            .host_name
+            .clone()
+            .unwrap_or(host_alias.clone());
+
+        let username = cli_one::<String>(matches, "username")
+            .or(values.username)
+            .or(ssh_host_config.user.clone())
+            .ok_or(
+            "The sftp username must be provided (via --username, 'username' in the config file or 'User' in the ssh config)",
+        )?;
+
+        // port and connection_count have default values, so unwrap is safe
+        let port = cli_one::<u16>(matches, "port")
+            .or(values.port)
+            .or(ssh_host_config.port)
+            .unwrap_or(*matches.get_one::<u16>("port").unwrap());
+        let connection_count = cli_one::<u8>(matches, "connection_count")
+            .or(values.connections)
+            .unwrap_or(*matches.get_one::<u8>("connection_count").unwrap());
+
+        // timeouts have default values, so unwrap is safe
+        let connection_options = ConnectionOptions {
+            connect_timeout: ConnectionOptions::secs_or_disabled(
+                cli_one::<u64>(matches, "connect_timeout")
+                    .or(values.connect_timeout)
+                    .unwrap_or(*matches.get_one::<u64>("connect_timeout").unwrap()),
+            ),
+            operation_timeout: ConnectionOptions::secs_or_disabled(
+                cli_one::<u64>(matches, "timeout")
+                    .or(values.timeout)
+                    .unwrap_or(*matches.get_one::<u64>("timeout").unwrap()),
+            ),
+            keepalive_interval: ConnectionOptions::secs_or_disabled(
+                cli_one::<u64>(matches, "keepalive")
+                    .or(values.keepalive)
+                    .unwrap_or(*matches.get_one::<u64>("keepalive").unwrap()),
+            ),
+        };
+
+        // the env vars are only read (and required) if no value is given via cli or config file
+        let password = match cli_one::<String>(matches, "password").or(values.password) {
+            Some(password) => Some(password),
+            None => read_env(values.password_env.as_deref())?,
+        };
+        let passphrase = match cli_one::<String>(matches, "passphrase").or(values.passphrase) {
+            Some(passphrase) => Some(passphrase),
+            None => read_env(values.passphrase_env.as_deref())?,
+        };
+        let pubkey = cli_one::<PathBuf>(matches, "pubkey").or(values
+            .pubkey
+            .map(|p| resolve_config_path(&p, &config_base_dir)));
+        let mut privkey = cli_one::<PathBuf>(matches, "privkey").or(values
+            .privkey
+            .map(|p| resolve_config_path(&p, &config_base_dir)));
+
+        // Auth fallbacks, only used if no other credentials are given explicitly:
+        // 1. the ssh-agent, if SSH_AUTH_SOCK is set
+        // 2. the first existing IdentityFile from the ssh config
+        let has_explicit_credentials = password.is_some() || pubkey.is_some() || privkey.is_some();
+        let use_agent = cli_flag(matches, "agent")
+            .or(values.agent)
+            .unwrap_or(!has_explicit_credentials && std::env::var_os("SSH_AUTH_SOCK").is_some());
+
+        let mut pubkey = pubkey;
+        if !has_explicit_credentials && !use_agent {
+            if let Some(identity_file) = ssh_host_config
+                .identity_files
+                .iter()
+                .find(|identity_file| identity_file.is_file())
+            {
+                let identity_pubkey = PathBuf::from(format!("{}.pub", identity_file.display()));
+                pubkey = identity_pubkey.is_file().then_some(identity_pubkey);
+                privkey = Some(identity_file.clone());
+            }
+        }
+
+        let host_key_mode =
+            match cli_one::<String>(matches, "host_key_checking").or(values.host_key_checking) {
+                Some(mode) => mode.parse::<HostKeyMode>()?,
+                None => HostKeyMode::AcceptNew,
+            };
+        let host_key_policy = HostKeyPolicy {
+            mode: host_key_mode,
+            known_hosts_file: cli_one::<PathBuf>(matches, "known_hosts").or(values
+                .known_hosts
+                .map(|p| resolve_config_path(&p, &config_base_dir))),
+            fingerprint: cli_one::<String>(matches, "host_key_fingerprint")
+                .or(values.host_key_fingerprint),
+        };
+
+        let ignore_includes = cli_many(matches, "watcher_ignore_path_includes")
+            .or(values.ignore_path_includes)
+            .unwrap_or_default();
+        let ignore_ends = cli_many(matches, "watcher_ignore_path_ends_with")
+            .or(values.ignore_path_ends)
+            .unwrap_or_default();
+        let include_globs = cli_many(matches, "include_globs")
+            .or(values.include)
+            .unwrap_or_default();
+        let exclude_globs = cli_many(matches, "exclude_globs")
+            .or(values.exclude)
+            .unwrap_or_default();
+        let respect_ignore_files = cli_flag(matches, "respect_ignore_files")
+            .or(values.respect_ignore_files)
+            .unwrap_or(false);
+
+        let largest_first = cli_flag(matches, "largest_first")
+            .or(values.largest_first)
+            .unwrap_or(false);
+
+        let sync_deletes = cli_flag(matches, "sync_deletes")
+            .or(values.sync_deletes)
+            .unwrap_or(false);
+        let prune_empty_dirs = cli_flag(matches, "prune_empty_dirs")
+            .or(values.prune_empty_dirs)
+            .unwrap_or(false);
+        let protected_paths = cli_many(matches, "protected_paths")
+            .or(values.protected_paths)
+            .unwrap_or_default();
+
+        let atomic_uploads = cli_flag(matches, "atomic")
+            .or(values.atomic)
+            .unwrap_or(false);
+        let stable_wait = cli_one::<u64>(matches, "stable_wait")
+            .or(values.stable_wait)
+            .filter(|ms| *ms > 0)
+            .map(Duration::from_millis);
+        let skip_unchanged = cli_flag(matches, "skip_unchanged")
+            .or(values.skip_unchanged)
+            .unwrap_or(false);
+        // the manifest belongs to the project => next to the config file (config_base_dir is the cwd without config file)
+        let manifest_file = cli_flag(matches, "manifest")
+            .or(values.manifest)
+            .unwrap_or(false)
+            .then(|| UploadManifest::default_path(&config_base_dir));
+        let dry_run = cli_flag(matches, "dry_run")
+            .or(values.dry_run)
+            .unwrap_or(false);
+
+        // debounce has a default value, so unwrap is safe
+        let debounce = Duration::from_millis(
+            cli_one::<u64>(matches, "debounce")
+                .or(values.debounce)
+                .unwrap_or(*matches.get_one::<u64>("debounce").unwrap()),
+        );
+        // --settle and --settle-marker are alternatives => the cli replaces both values of the config file
+        let (settle, settle_marker) = match (
+            cli_one::<u64>(matches, "settle"),
+            cli_one::<PathBuf>(matches, "settle_marker"),
+        ) {
+            (None, None) => (values.settle, values.settle_marker),
+            cli_values => cli_values,
+        };
+        let settle_mode = match (settle, settle_marker) {
+            (Some(_), Some(_)) => {
+                return Err("'settle' and 'settle_marker' cannot be combined".to_string())
+            }
+            (Some(quiet_ms), None) => Some(SettleMode::Quiet(Duration::from_millis(quiet_ms))),
+            (None, Some(marker)) => Some(SettleMode::Marker(marker)),
+            (None, None) => None,
+        };
+
+        let upload_initial = cli_flag(matches, "upload_initial")
+            .or(values.upload_initial)
+            .unwrap_or(false);
+
+        let verbosity = match (
+            cli_flag(matches, "quiet").or(values.quiet).unwrap_or(false),
+            cli_flag(matches, "verbose")
+                .or(values.verbose)
+                .unwrap_or(false),
+        ) {
+            (true, true) => return Err("'quiet' and 'verbose' cannot be combined".to_string()),
+            (true, false) => Verbosity::Quiet,
+            (false, true) => Verbosity::Verbose,
+            (false, false) => Verbosity::Normal,
+        };
+        // output has a default value, so unwrap is safe
+        let output = cli_one::<String>(matches, "output")
+            .or(values.output)
+            .unwrap_or(matches.get_one::<String>("output").unwrap().clone());
+        let output_file = cli_one::<PathBuf>(matches, "output_file").or(values
+            .output_file
+            .map(|p| resolve_config_path(&p, &config_base_dir)));
+        let json_events = match (output.as_str(), output_file) {
+            ("text", None) => None,
+            ("text", Some(_)) => return Err("'output_file' requires '--output json'".to_string()),
+            ("json", None) => Some(EventTarget::Stdout),
+            ("json", Some(output_file)) => Some(EventTarget::File(output_file)),
+            (other, _) => return Err(format!("Unknown output format '{}'", other)),
+        };
+
+        // progressbars produce garbage in CI logs and pipes => only draw them on an interactive terminal
+        let is_terminal = std::io::stdout().is_terminal() && std::io::stderr().is_terminal();
+        let plain = cli_flag(matches, "plain").or(values.plain).unwrap_or(false);
+        let progress_bars = is_terminal
+            && !plain
+            && verbosity != Verbosity::Quiet
+            && json_events != Some(EventTarget::Stdout);
+
+        Ok(Settings {
+            config_file: config_path,
+            upload_pairs,
+            host,
+            host_alias,
+            port,
+            username,
+            pubkey,
+            privkey,
+            passphrase,
+            password,
+            use_agent,
+            host_key_policy,
+            connection_count,
+            largest_first,
+            sync_deletes,
+            prune_empty_dirs,
+            protected_paths,
+            atomic_uploads,
+            skip_unchanged,
+            stable_wait,
+            manifest_file,
+            dry_run,
+            connection_options,
+            ignore_includes,
+            ignore_ends,
+            include_globs,
+            exclude_globs,
+            respect_ignore_files,
+            debounce,
+            settle_mode,
+            upload_initial,
+            verbosity,
+            progress_bars,
+            json_events,
+        })
+    }
+}
+
+/**
+ * Returns the value of an arg only if it was explicitly given on the command line (ignores clap defaults)
+ */
+fn cli_one<T: Clone + Send + Sync + 'static>(matches: &ArgMatches, id: &str) -> Option<T> {
+    match matches.value_source(id) {
+        Some(ValueSource::CommandLine) => matches.get_one::<T>(id).cloned(),
+        _ => None,
+    }
+}
+
+fn cli_many(matches: &ArgMatches, id: &str) -> Option<Vec<String>> {
+    match matches.value_source(id) {
+        Some(ValueSource::CommandLine) => Some(
+            matches
+                .get_many::<String>(id)
+                .unwrap_or_default()
+                .cloned()
+                .collect(),
+        ),
+        _ => None,
+    }
+}
+
+fn cli_flag(matches: &ArgMatches, id: &str) -> Option<bool> {
+    match matches.value_source(id) {
+        Some(ValueSource::CommandLine) => Some(matches.get_flag(id)),
+        _ => None,
+    }
+}
+
+fn read_env(var_name: Option<&str>) -> Result<Option<String>, String> {
+    match var_name {
+        None => Ok(None),
+        Some(var_name) => std::env::var(var_name).map(Some).map_err(|e| {
+            format!(
+                "Cannot read environment variable '{}' referenced in the config file: {}",
+                var_name, e
+            )
+        }),
+    }
+}
+
+/**
+ * Reads the ssh config (the given one or ~/.ssh/config) and returns the values for the given host alias.
+ * A missing default ssh config is not an error, a missing explicitly given one is.
+ */
+fn read_ssh_host_config(
+    explicit_path: Option<&Path>,
+    host_alias: &str,
+) -> Result<SshHostConfig, String> {
+    let path = match explicit_path {
+        Some(path) => path.to_path_buf(),
+        None => match SshConfig::default_path() {
+            Some(path) if path.is_file() => path,
+            _ => return Ok(SshHostConfig::default()),
+        },
+    };
+
+    match SshConfig::load(&path) {
+        Ok(ssh_config) => Ok(ssh_config.query(host_alias)),
+        Err(e) => Err(format!("Cannot read ssh config {}: {}", path.display(), e)),
+    }
+}
+
+/**
+ * Resolves '~/' against the home dir and relative paths against the config file dir
+ */
+fn resolve_config_path(path: &Path, config_base_dir: &Path) -> PathBuf {
+    if let Ok(stripped) = path.strip_prefix("~") {
+        if let Some(home_dir) = home::home_dir() {
+            return home_dir.join(stripped);
+        }
+    }
+
+    if path.is_relative() {
+        config_base_dir.join(path)
+    } else {
+        path.to_path_buf()
+    }
+}
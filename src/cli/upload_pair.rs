@@ -1,6 +1,6 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct UploadPair {
     pub source: PathBuf,
     pub target: PathBuf,
@@ -33,4 +33,22 @@ impl UploadPair {
 
         UploadPair::new(PathBuf::from(source), target)
     }
+
+    /**
+     * Same as from_uploadpair_string, but resolves a relative source against base_dir
+     * (used for upload pairs from the config file, which are relative to the config file).
+     * If no target is given, the relative source is used as target, like in UploadPair::new.
+     */
+    pub fn from_uploadpair_string_with_base(upload_pair_string: &str, base_dir: &Path) -> Self {
+        let pair = UploadPair::from_uploadpair_string(upload_pair_string);
+
+        if pair.source.is_absolute() {
+            return pair;
+        }
+
+        UploadPair {
+            source: base_dir.join(&pair.source),
+            target: pair.target,
+        }
+    }
 }
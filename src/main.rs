@@ -1,9 +1,18 @@
+use cli::config_file::ConfigFile;
+use cli::settings::Settings;
 use cli::setup_cli;
 use cli::upload_pair::UploadPair;
-use std::path::PathBuf;
-use uploader::upload_actor::AuthMethod;
+use miette::{miette, IntoDiagnostic, WrapErr};
+use std::{path::PathBuf, time::Instant};
+use uploader::progress_actor::{OutputOptions, Verbosity};
+use uploader::upload_actor::{AuthMethod, ConnectionConfig, UploadCounts, UploadOptions};
 use uploader::upload_actor_handle::UploadActorHandle;
-use watcher::watch_actor_handle::start_watching;
+use uploader::upload_event::EventTarget;
+use uploader::upload_manifest::UploadManifest;
+use uploader::upload_queue::QueueOrder;
+use watcher::path_filter::PathFilter;
+use watcher::watch_actor::FileChange;
+use watcher::watch_actor_handle::{collect_files, start_watching};
 
 mod cli;
 mod sftp;
@@ -11,115 +20,284 @@ mod uploader;
 mod utils;
 mod watcher;
 
-fn main() {
+fn main() -> miette::Result<()> {
     let cli = setup_cli();
     let matches = cli.get_matches();
 
-    // upload_pair is required, so unwrap is safe
-    let upload_pair =
-        UploadPair::from_uploadpair_string(matches.get_one::<String>("upload_pair").unwrap());
-    println!("upload_pair: {:?}", upload_pair);
+    // Step 0: Read the config file (if any) and merge it with the cli flags
+    let config_file = ConfigFile::discover(
+        matches.get_one::<PathBuf>("config").map(|p| p.as_path()),
+        matches.get_one::<String>("profile").map(|p| p.as_str()),
+    )?;
 
-    // connection_count has a default value, so unwrap is safe
-    let connection_count = matches.get_one::<u8>("connection_count").unwrap();
-    println!("connection_count: {:?}", connection_count);
+    let settings = Settings::resolve(&matches, config_file).map_err(|e| miette!("{}", e))?;
+    // stdout is reserved for the json events with --output json (without --output-file)
+    let text_to_stderr = settings.json_events == Some(EventTarget::Stdout);
+    if settings.verbosity == Verbosity::Verbose {
+        print_text(text_to_stderr, &format!("{:#?}", settings));
+    }
 
-    // host is required, so unwrap is safe
-    let sftp_host = matches.get_one::<String>("host").unwrap();
-    println!("sftp_host: {:?}", sftp_host);
+    // check if any auth method is provided
+    if !settings.use_agent && settings.password.is_none() && settings.privkey.is_none() {
+        return Err(miette!(
+            help = "Use --password, --privkey (and optionally --pubkey) or --agent, or set them in the config file.",
+            "No authentication method provided"
+        ));
+    }
 
-    // port is required, so unwrap is safe
-    let sftp_port = matches.get_one::<u16>("port").unwrap();
-    println!("sftp_port: {:?}", sftp_port);
+    // push subcommand: upload everything once, without watching
+    let is_push = matches.subcommand_name() == Some("push");
 
-    let ignore_includes = matches
-        .get_many::<String>("watcher_ignore_path_includes")
-        .unwrap_or_default()
-        .map(|v| v.as_str())
-        .collect::<Vec<_>>();
-    println!("ignore_includes: {:?}", ignore_includes);
+    // Step 0.1: Build the path filter of each upload pair (shared by the watcher and the initial walk)
+    let mut filtered_pairs = vec![];
+    for upload_pair in settings.upload_pairs.iter() {
+        let path_filter = PathFilter::new(
+            &upload_pair.source,
+            settings.ignore_includes.clone(),
+            settings.ignore_ends.clone(),
+            &settings.include_globs,
+            &settings.exclude_globs,
+            settings.respect_ignore_files,
+        )
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Error building the filter for {:?}", upload_pair.source))?;
+        filtered_pairs.push((upload_pair.clone(), path_filter));
+    }
 
-    let ignore_ends = matches
-        .get_many::<String>("watcher_ignore_path_ends_with")
-        .unwrap_or_default()
-        .map(|v| v.as_str())
-        .collect::<Vec<_>>();
-    println!("ignore_ends: {:?}", ignore_ends);
+    // Setp 1: Setup one watcher thread per upload pair
+    let mut watched_pairs = vec![];
+    for (upload_pair, path_filter) in filtered_pairs.iter().filter(|_| !is_push) {
+        let rx_files_to_upload = start_watching(
+            upload_pair.source.clone(),
+            settings.upload_initial,
+            path_filter.clone(),
+            settings.sync_deletes,
+            settings.debounce,
+            settings.settle_mode.clone(),
+        )
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Error watching directory {:?}", upload_pair.source))?;
+        watched_pairs.push((upload_pair.clone(), rx_files_to_upload));
+    }
 
-    // username is required, so unwrap is safe
-    let sftp_username = matches.get_one::<String>("username").unwrap();
-    println!("sftp_username: {:?}", sftp_username);
+    // Step 2: Setup uploader thread
+    let auth_method = match (settings.use_agent, settings.privkey) {
+        (true, _) => AuthMethod::Agent,
+        (false, Some(privkey)) => AuthMethod::Pubkey(settings.pubkey, privkey, settings.passphrase),
+        (false, None) => AuthMethod::Password(settings.password.unwrap()),
+    };
 
-    // Read all possible auth inputs
-    let pubkey = matches.get_one::<PathBuf>("pubkey");
-    println!("pubkey: {:?}", pubkey);
+    // the manifest is only a cache => start with an empty one, if it cannot be read
+    let manifest = settings.manifest_file.as_ref().map(|manifest_file| {
+        let host_id = format!("{}@{}:{}", settings.username, settings.host, settings.port);
+        UploadManifest::load(manifest_file, &host_id).unwrap_or_else(|e| {
+            eprintln!("{:?}", miette::Report::new(e));
+            UploadManifest::empty(manifest_file.clone(), &host_id)
+        })
+    });
 
-    let privkey = matches.get_one::<PathBuf>("privkey");
-    println!("privkey: {:?}", privkey);
+    let event_writer = match settings.json_events.as_ref() {
+        Some(event_target) => Some(
+            event_target
+                .open()
+                .into_diagnostic()
+                .wrap_err_with(|| format!("Cannot open the json output {:?}", event_target))?,
+        ),
+        None => None,
+    };
 
-    let passphrase = matches.get_one::<String>("passphrase");
-    println!("passphrase: {:?}", "******");
+    // Note: All upload pairs share this one handle (and therefore the same connection pool)
+    let uploader_handle = UploadActorHandle::new(
+        settings.connection_count,
+        ConnectionConfig {
+            host: settings.host,
+            port: settings.port,
+            username: settings.username,
+            auth_method,
+            host_key_policy: settings.host_key_policy,
+            connection_options: settings.connection_options,
+        },
+        UploadOptions {
+            queue_order: match settings.largest_first {
+                true => QueueOrder::LargestFirst,
+                false => QueueOrder::Detected,
+            },
+            prune_empty_dirs: settings.prune_empty_dirs,
+            protected_paths: settings.protected_paths,
+            atomic_uploads: settings.atomic_uploads,
+            skip_unchanged: settings.skip_unchanged,
+            dry_run: settings.dry_run,
+            stable_wait: settings.stable_wait,
+            output: OutputOptions {
+                verbosity: settings.verbosity,
+                progress_bars: settings.progress_bars,
+                text_to_stderr,
+            },
+        },
+        manifest,
+        event_writer,
+    )?;
 
-    let password = matches.get_one::<String>("password");
-    println!("password: {:?}", "******");
+    // Step 2.1: Remove temp files of crashed atomic uploads
+    // (sent before the first batch of the watchers, so it runs before any upload)
+    if settings.atomic_uploads {
+        for upload_pair in settings.upload_pairs.iter() {
+            if let Err(e) = uploader_handle
+                .clone()
+                .cleanup_temp_files(Some(upload_pair.target.clone()))
+            {
+                eprintln!(
+                    "Error sending temp file cleanup to the upload actor: {:?}",
+                    e.to_string()
+                );
+            }
+        }
+    }
 
-    // check if any auth method is provided
-    if password.is_none() && (pubkey.is_none() || privkey.is_none()) {
-        panic!("Either password or pubkey and privkey must be provided!");
+    if is_push {
+        return push(uploader_handle, filtered_pairs, text_to_stderr);
     }
 
-    // Read extra flags
-    let upload_initial = matches.get_one::<bool>("upload_initial").unwrap();
-    println!("upload_initial: {:?}", upload_initial);
-
-    // Setp 1: Setup watcher thread
-    let rx_files_to_upload = match start_watching(
-        upload_pair.source.clone(),
-        upload_initial.clone(),
-        ignore_includes
-            .into_iter()
-            .map(|s| String::from(s))
-            .collect(),
-        ignore_ends.into_iter().map(|s| String::from(s)).collect(),
-    ) {
-        Ok(rx) => rx,
-        Err(e) => panic!("Error watching directory: {:?}", e),
-    };
+    // Step 3: Start one loop per upload pair and send files from its watcher to the uploader
+    let mut forwarder_tasks = vec![];
+    for (i, (upload_pair, rx_files_to_upload)) in watched_pairs.into_iter().enumerate() {
+        let mut pair_uploader_handle = uploader_handle.clone();
+        let thread = std::thread::Builder::new().name(format!("upload_pair_{}", i + 1));
 
-    // Step 2: Setup uploader thread
-    let auth_method = match pubkey.as_ref() {
-        Some(p) => AuthMethod::Pubkey(
-            p.to_path_buf(),
-            privkey.as_ref().unwrap().to_path_buf(),
-            None,
-        ),
-        None => AuthMethod::Password(password.as_ref().unwrap().to_string()),
-    };
+        let task = thread.spawn(move || {
+            while let Ok(file_changes) = rx_files_to_upload.recv() {
+                // println!(
+                //     "Debug: Files received from watcher channel: {:?}",
+                //     file_changes.len()
+                // );
+                let (files_to_upload, files_to_remove, renames) = split_file_changes(file_changes);
+                let remote_dir = Some(upload_pair.target.clone());
+                let local_base_dir = Some(upload_pair.source.clone());
+
+                // renames first, since the uploads and removes of this batch can only affect other paths
+                if !renames.is_empty() {
+                    if let Err(e) = pair_uploader_handle.rename_files(
+                        renames,
+                        remote_dir.clone(),
+                        local_base_dir.clone(),
+                    ) {
+                        eprintln!(
+                            "Error sending renames to the upload actor: {:?}",
+                            e.to_string()
+                        );
+                    }
+                }
+
+                if let Err(e) = pair_uploader_handle.upload_files(
+                    files_to_upload,
+                    remote_dir.clone(),
+                    local_base_dir.clone(),
+                ) {
+                    eprintln!(
+                        "Error sending files for uploading to the upload actor: {:?}",
+                        e.to_string()
+                    );
+                }
+                if files_to_remove.is_empty() {
+                    continue;
+                }
+                if let Err(e) =
+                    pair_uploader_handle.remove_files(files_to_remove, remote_dir, local_base_dir)
+                {
+                    eprintln!(
+                        "Error sending files for removing to the upload actor: {:?}",
+                        e.to_string()
+                    );
+                }
+            }
+        });
 
-    let mut uploader_handle = UploadActorHandle::new(
-        connection_count.clone(),
-        sftp_host.to_string(),
-        sftp_port.clone(),
-        sftp_username.to_string(),
-        auth_method,
+        match task {
+            Ok(task) => forwarder_tasks.push(task),
+            Err(e) => eprintln!("Error spawning upload pair thread: {:?}", e),
+        }
+    }
+
+    // Step 4: Block the main thread until all watchers are closed
+    for task in forwarder_tasks {
+        if let Err(e) = task.join() {
+            eprintln!("Error joining upload pair thread: {:?}", e);
+        }
+    }
+
+    Ok(())
+}
+
+/**
+ * The push subcommand: uploads all files of all upload pairs once and prints a summary.
+ * Returns an error (=> non-zero exit code) if any file failed.
+ */
+fn push(
+    mut uploader_handle: UploadActorHandle,
+    filtered_pairs: Vec<(UploadPair, PathFilter)>,
+    text_to_stderr: bool,
+) -> miette::Result<()> {
+    let started_at = Instant::now();
+    let mut total_counts = UploadCounts::default();
+
+    for (upload_pair, path_filter) in filtered_pairs {
+        let files = collect_files(&upload_pair.source, &path_filter);
+        let counts = uploader_handle
+            .push_files(
+                files,
+                Some(upload_pair.target.clone()),
+                Some(upload_pair.source.clone()),
+            )
+            .into_diagnostic()
+            .wrap_err("The upload actor stopped unexpectedly")?;
+        let pair_summary = format!(
+            "{} -> {}: {}",
+            upload_pair.source.display(),
+            upload_pair.target.display(),
+            counts
+        );
+        print_text(text_to_stderr, &pair_summary);
+        total_counts.add(&counts);
+    }
+
+    let push_summary = format!(
+        "Push finished in {:.1}s: {}",
+        started_at.elapsed().as_secs_f64(),
+        total_counts
     );
+    print_text(text_to_stderr, &push_summary);
+
+    if total_counts.failed > 0 {
+        return Err(miette!("{} files failed to upload", total_counts.failed));
+    }
+    Ok(())
+}
+
+/**
+ * Prints the text output outside of the upload actor, to stderr if stdout is used for the json events
+ */
+fn print_text(text_to_stderr: bool, text: &str) {
+    match text_to_stderr {
+        true => eprintln!("{}", text),
+        false => println!("{}", text),
+    }
+}
 
-    // Step 3: Start the main loop and send files from watcher to uploader
-    while let Ok(files_to_upload) = rx_files_to_upload.recv() {
-        // println!(
-        //     "Debug: Files received from watcher channel: {:?}",
-        //     files_to_upload.len()
-        // );
-        let remote_dir = Some(upload_pair.target.clone());
-        if let Err(e) = uploader_handle.upload_files(
-            files_to_upload,
-            remote_dir,
-            Some(upload_pair.source.clone()),
-        ) {
-            eprintln!(
-                "Error sending files for uploading to the upload actor: {:?}",
-                e.to_string()
-            );
+/**
+ * Splits the changes of one watcher batch into (files to upload, files to remove, renames)
+ */
+fn split_file_changes(
+    file_changes: Vec<FileChange>,
+) -> (Vec<PathBuf>, Vec<PathBuf>, Vec<(PathBuf, PathBuf)>) {
+    let mut files_to_upload = vec![];
+    let mut files_to_remove = vec![];
+    let mut renames = vec![];
+    for change in file_changes {
+        match change {
+            FileChange::Upload(path) => files_to_upload.push(path),
+            FileChange::Remove(path) => files_to_remove.push(path),
+            FileChange::Rename { from, to } => renames.push((from, to)),
         }
     }
+    (files_to_upload, files_to_remove, renames)
 }
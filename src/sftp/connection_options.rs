@@ -0,0 +1,42 @@
+use std::time::Duration;
+
+/**
+ * Timeouts and keepalive of one sftp connection.
+ * A value of None disables the respective timeout / the keepalive.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConnectionOptions {
+    /**
+     * Max time to establish the TCP connection to the server
+     */
+    pub connect_timeout: Option<Duration>,
+    /**
+     * Max time a single blocking ssh operation (handshake, auth, sftp request, write of a chunk) may take.
+     * A stalled transfer fails with SftpClientError::Timeout after this time.
+     */
+    pub operation_timeout: Option<Duration>,
+    /**
+     * Interval for ssh keepalive messages, to detect half-open connections
+     * and to prevent NAT routers / firewalls from dropping idle connections
+     */
+    pub keepalive_interval: Option<Duration>,
+}
+
+impl Default for ConnectionOptions {
+    fn default() -> Self {
+        ConnectionOptions {
+            connect_timeout: Some(Duration::from_secs(10)),
+            operation_timeout: Some(Duration::from_secs(30)),
+            keepalive_interval: Some(Duration::from_secs(15)),
+        }
+    }
+}
+
+impl ConnectionOptions {
+    /**
+     * Converts a number of seconds from cli or config file, 0 means disabled
+     */
+    pub fn secs_or_disabled(secs: u64) -> Option<Duration> {
+        (secs > 0).then(|| Duration::from_secs(secs))
+    }
+}
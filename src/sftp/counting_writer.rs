@@ -0,0 +1,48 @@
+use std::io::Write;
+
+/**
+ * Wraps a writer and reports the number of bytes of every successful write,
+ * used to show the byte progress of an upload
+ */
+pub struct CountingWriter<W: Write, F: Fn(u64)> {
+    inner: W,
+    on_write: F,
+}
+
+impl<W: Write, F: Fn(u64)> CountingWriter<W, F> {
+    pub fn new(inner: W, on_write: F) -> Self {
+        CountingWriter { inner, on_write }
+    }
+}
+
+impl<W: Write, F: Fn(u64)> Write for CountingWriter<W, F> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        (self.on_write)(written as u64);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    #[test]
+    fn test_counts_written_bytes() {
+        let total = Cell::new(0);
+        let mut target = vec![];
+        let mut writer =
+            CountingWriter::new(&mut target, |written| total.set(total.get() + written));
+
+        std::io::copy(&mut "hello world".as_bytes(), &mut writer).unwrap();
+        writer.flush().unwrap();
+
+        assert_eq!(total.get(), 11);
+        assert_eq!(target, b"hello world");
+    }
+}
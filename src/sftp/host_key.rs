@@ -0,0 +1,521 @@
+use base64::{
+    engine::general_purpose::{STANDARD, STANDARD_NO_PAD},
+    Engine,
+};
+use sha1::{Digest, Sha1};
+use ssh2::{
+    CheckResult, HashType, HostKeyType, KnownHostFileKind, KnownHosts, MethodType, Session,
+};
+use std::{
+    fmt,
+    io::Write,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Mutex,
+};
+
+use super::sftp_client::SftpClientError;
+
+/**
+ * All SftpClients of this process verify (and maybe write) the same known_hosts file in parallel threads.
+ * This lock makes sure that accept-new only adds one entry, even if all connections see an unknown host at once.
+ */
+static KNOWN_HOSTS_LOCK: Mutex<()> = Mutex::new(());
+
+/**
+ * How to handle the host key of the server, similar to the StrictHostKeyChecking option of OpenSSH
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HostKeyMode {
+    /**
+     * Refuse unknown and changed host keys
+     */
+    Strict,
+    /**
+     * Trust on first use: add unknown host keys to the known_hosts file, refuse changed host keys
+     */
+    AcceptNew,
+    /**
+     * Do not verify the host key at all (NOT recommended)
+     */
+    Off,
+}
+
+impl FromStr for HostKeyMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "strict" | "yes" => Ok(HostKeyMode::Strict),
+            "accept-new" => Ok(HostKeyMode::AcceptNew),
+            "off" | "no" => Ok(HostKeyMode::Off),
+            other => Err(format!(
+                "Invalid host key checking mode '{}', expected one of: strict, accept-new, off",
+                other
+            )),
+        }
+    }
+}
+
+impl fmt::Display for HostKeyMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HostKeyMode::Strict => write!(f, "strict"),
+            HostKeyMode::AcceptNew => write!(f, "accept-new"),
+            HostKeyMode::Off => write!(f, "off"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct HostKeyPolicy {
+    pub mode: HostKeyMode,
+    /**
+     * The known_hosts file to check against (and to write new keys to in accept-new mode).
+     * Default: ~/.ssh/known_hosts
+     */
+    pub known_hosts_file: Option<PathBuf>,
+    /**
+     * Optional: A pinned fingerprint, like `SHA256:abc...` (as printed by `ssh-keygen -lf`).
+     * If given, the host key must have exactly this fingerprint and the known_hosts file is not used.
+     */
+    pub fingerprint: Option<String>,
+}
+
+impl Default for HostKeyPolicy {
+    fn default() -> Self {
+        HostKeyPolicy {
+            mode: HostKeyMode::AcceptNew,
+            known_hosts_file: None,
+            fingerprint: None,
+        }
+    }
+}
+
+impl HostKeyPolicy {
+    fn known_hosts_path(&self) -> Option<PathBuf> {
+        self.known_hosts_file
+            .clone()
+            .or_else(|| home::home_dir().map(|home| home.join(".ssh").join("known_hosts")))
+    }
+}
+
+/**
+ * Returns the SHA256 fingerprint of the server host key in OpenSSH format: `SHA256:<base64 without padding>`
+ */
+pub fn host_key_fingerprint(session: &Session) -> Option<String> {
+    session
+        .host_key_hash(HashType::Sha256)
+        .map(|hash| format!("SHA256:{}", STANDARD_NO_PAD.encode(hash)))
+}
+
+/**
+ * Verifies the host key of an ssh session after the handshake and before authentication
+ */
+pub fn verify_host_key(
+    session: &Session,
+    host: &str,
+    port: u16,
+    policy: &HostKeyPolicy,
+) -> Result<(), SftpClientError> {
+    let (key, key_type) = session
+        .host_key()
+        .ok_or_else(|| SftpClientError::HostKeyMissing {
+            host: host.to_string(),
+        })?;
+    let fingerprint = host_key_fingerprint(session).unwrap_or_default();
+
+    // Case 1: pinned fingerprint => known_hosts is not needed
+    if let Some(expected) = policy.fingerprint.as_ref() {
+        if normalize_fingerprint(expected) == normalize_fingerprint(&fingerprint) {
+            return Ok(());
+        }
+        return Err(SftpClientError::HostKeyFingerprintMismatch {
+            host: host.to_string(),
+            expected: expected.clone(),
+            actual: fingerprint,
+        });
+    }
+
+    if policy.mode == HostKeyMode::Off {
+        return Ok(());
+    }
+
+    // Case 2: check against known_hosts
+    let known_hosts_path =
+        policy
+            .known_hosts_path()
+            .ok_or_else(|| SftpClientError::KnownHostsError {
+                msg: String::from("Cannot determine home dir to find ~/.ssh/known_hosts"),
+                path: PathBuf::from("~/.ssh/known_hosts"),
+            })?;
+
+    let _lock = KNOWN_HOSTS_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+
+    let mut known_hosts = session
+        .known_hosts()
+        .map_err(|e| SftpClientError::KnownHostsError {
+            msg: format!("Cannot init known hosts: {}", e),
+            path: known_hosts_path.clone(),
+        })?;
+    if known_hosts_path.is_file() {
+        known_hosts
+            .read_file(&known_hosts_path, KnownHostFileKind::OpenSSH)
+            .map_err(|e| SftpClientError::KnownHostsError {
+                msg: format!("Cannot read known_hosts file: {}", e),
+                path: known_hosts_path.clone(),
+            })?;
+    }
+
+    match check_known_host(&known_hosts, &known_hosts_path, host, port, key, key_type) {
+        CheckResult::Match => Ok(()),
+        CheckResult::Mismatch => Err(SftpClientError::HostKeyChanged {
+            host: host.to_string(),
+            fingerprint,
+            known_hosts_file: known_hosts_path,
+        }),
+        CheckResult::NotFound => match policy.mode {
+            HostKeyMode::AcceptNew => {
+                append_known_host(&known_hosts_path, host, port, key, key_type)
+            }
+            _ => Err(SftpClientError::HostKeyUnknown {
+                host: host.to_string(),
+                fingerprint,
+                known_hosts_file: known_hosts_path,
+            }),
+        },
+        CheckResult::Failure => Err(SftpClientError::KnownHostsError {
+            msg: format!("Failed to check the host key of {}", host),
+            path: known_hosts_path,
+        }),
+    }
+}
+
+/**
+ * Restricts the host key algorithms of the handshake to the key types which are recorded for the host
+ * in the known_hosts file, so that the server sends a key which can be verified.
+ * (Otherwise libssh2 negotiates its own first preference, e.g. ecdsa, even if only the ed25519 key is known.)
+ * Must be called before Session::handshake().
+ */
+pub fn prefer_known_host_key_types(
+    session: &Session,
+    host: &str,
+    port: u16,
+    policy: &HostKeyPolicy,
+) -> Result<(), ssh2::Error> {
+    if policy.mode == HostKeyMode::Off || policy.fingerprint.is_some() {
+        return Ok(());
+    }
+    let Some(known_hosts_path) = policy.known_hosts_path() else {
+        return Ok(());
+    };
+    let known_key_types = known_key_types(&known_hosts_path, host, port);
+
+    // keep the preference order of libssh2
+    let preferred_algs: Vec<&str> = session
+        .supported_algs(MethodType::HostKey)?
+        .into_iter()
+        .filter(|alg| {
+            known_key_types
+                .iter()
+                .any(|key_type| key_type_of_host_key_alg(alg) == key_type)
+        })
+        .collect();
+    if preferred_algs.is_empty() {
+        return Ok(());
+    }
+    session.method_pref(MethodType::HostKey, &preferred_algs.join(","))
+}
+
+/**
+ * libssh2 compares the host key against the known_hosts entries of ALL key types.
+ * A host which is only known with another key type (e.g. ed25519, while ecdsa was negotiated)
+ * would be reported as a changed key => treat it as unknown instead
+ */
+fn check_known_host(
+    known_hosts: &KnownHosts,
+    known_hosts_path: &Path,
+    host: &str,
+    port: u16,
+    key: &[u8],
+    key_type: HostKeyType,
+) -> CheckResult {
+    match known_hosts.check_port(host, port, key) {
+        CheckResult::Mismatch => {
+            let is_known_key_type = key_type_name(key_type).is_some_and(|name| {
+                known_key_types(known_hosts_path, host, port)
+                    .iter()
+                    .any(|known| known == name)
+            });
+            match is_known_key_type {
+                true => CheckResult::Mismatch,
+                false => CheckResult::NotFound,
+            }
+        }
+        result => result,
+    }
+}
+
+/**
+ * The key types (e.g. `ssh-ed25519`) of the known_hosts entries for the host, including hashed entries
+ */
+fn known_key_types(known_hosts_path: &Path, host: &str, port: u16) -> Vec<String> {
+    let Ok(content) = std::fs::read_to_string(known_hosts_path) else {
+        return vec![];
+    };
+    let entry_name = known_hosts_entry_name(host, port);
+
+    let mut key_types = vec![];
+    for line in content.lines() {
+        let mut fields = line.split_whitespace();
+        let (Some(hosts), Some(key_type)) = (fields.next(), fields.next()) else {
+            continue;
+        };
+        // comments and marker lines (@cert-authority, @revoked) are no plain host keys
+        if hosts.starts_with('#') || hosts.starts_with('@') {
+            continue;
+        }
+        if matches_known_hosts_field(hosts, &entry_name) && !key_types.iter().any(|k| k == key_type)
+        {
+            key_types.push(key_type.to_string());
+        }
+    }
+    key_types
+}
+
+/**
+ * The host field is either a comma-separated list of names or a hashed name: `|1|<base64 salt>|<base64 hmac-sha1>`
+ */
+fn matches_known_hosts_field(hosts: &str, entry_name: &str) -> bool {
+    match hosts.strip_prefix("|1|") {
+        Some(hashed) => {
+            let Some((salt, hash)) = hashed.split_once('|') else {
+                return false;
+            };
+            match (STANDARD.decode(salt), STANDARD.decode(hash)) {
+                (Ok(salt), Ok(hash)) => hmac_sha1(&salt, entry_name.as_bytes()) == hash.as_slice(),
+                _ => false,
+            }
+        }
+        None => hosts
+            .split(',')
+            .any(|name| name.eq_ignore_ascii_case(entry_name)),
+    }
+}
+
+/**
+ * HMAC-SHA1 (RFC 2104), as used by OpenSSH for hashed known_hosts names
+ */
+fn hmac_sha1(key: &[u8], message: &[u8]) -> [u8; 20] {
+    const BLOCK_SIZE: usize = 64;
+    let mut block_key = [0u8; BLOCK_SIZE];
+    if key.len() > BLOCK_SIZE {
+        block_key[..20].copy_from_slice(&Sha1::digest(key));
+    } else {
+        block_key[..key.len()].copy_from_slice(key);
+    }
+
+    let mut inner = Sha1::new();
+    inner.update(block_key.map(|b| b ^ 0x36));
+    inner.update(message);
+    let mut outer = Sha1::new();
+    outer.update(block_key.map(|b| b ^ 0x5c));
+    outer.update(inner.finalize());
+    outer.finalize().into()
+}
+
+/**
+ * The rsa-sha2 signature algorithms use the same (ssh-rsa) keys
+ */
+fn key_type_of_host_key_alg(alg: &str) -> &str {
+    match alg {
+        "rsa-sha2-256" | "rsa-sha2-512" => "ssh-rsa",
+        other => other,
+    }
+}
+
+/**
+ * Accepts fingerprints with or without `SHA256:` prefix and base64 padding
+ */
+fn normalize_fingerprint(fingerprint: &str) -> &str {
+    let trimmed = fingerprint.trim();
+    trimmed
+        .strip_prefix("SHA256:")
+        .unwrap_or(trimmed)
+        .trim_end_matches('=')
+}
+
+/**
+ * Same format as OpenSSH: hosts with non-default ports are written as `[host]:port`
+ */
+fn known_hosts_entry_name(host: &str, port: u16) -> String {
+    if port == 22 {
+        host.to_string()
+    } else {
+        format!("[{}]:{}", host, port)
+    }
+}
+
+fn key_type_name(key_type: HostKeyType) -> Option<&'static str> {
+    match key_type {
+        HostKeyType::Rsa => Some("ssh-rsa"),
+        HostKeyType::Dss => Some("ssh-dss"),
+        HostKeyType::Ecdsa256 => Some("ecdsa-sha2-nistp256"),
+        HostKeyType::Ecdsa384 => Some("ecdsa-sha2-nistp384"),
+        HostKeyType::Ecdsa521 => Some("ecdsa-sha2-nistp521"),
+        HostKeyType::Ed25519 => Some("ssh-ed25519"),
+        HostKeyType::Unknown => None,
+    }
+}
+
+/**
+ * Appends the host key to the known_hosts file (instead of rewriting the whole file via libssh2,
+ * which would drop comments and formatting of the existing file)
+ */
+fn append_known_host(
+    path: &Path,
+    host: &str,
+    port: u16,
+    key: &[u8],
+    key_type: HostKeyType,
+) -> Result<(), SftpClientError> {
+    let key_type_name =
+        key_type_name(key_type).ok_or_else(|| SftpClientError::KnownHostsError {
+            msg: format!("Cannot store host key of unknown type for {}", host),
+            path: path.to_path_buf(),
+        })?;
+    let line = format!(
+        "{} {} {}\n",
+        known_hosts_entry_name(host, port),
+        key_type_name,
+        STANDARD.encode(key)
+    );
+
+    let to_known_hosts_error = |e: std::io::Error| SftpClientError::KnownHostsError {
+        msg: format!("Cannot add host key for {}: {}", host, e),
+        path: path.to_path_buf(),
+    };
+
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).map_err(to_known_hosts_error)?;
+    }
+
+    // make sure the new entry starts on its own line
+    let needs_newline = std::fs::read(path)
+        .map(|content| !content.is_empty() && !content.ends_with(b"\n"))
+        .unwrap_or(false);
+
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(to_known_hosts_error)?;
+    if needs_newline {
+        file.write_all(b"\n").map_err(to_known_hosts_error)?;
+    }
+    file.write_all(line.as_bytes())
+        .map_err(to_known_hosts_error)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_fingerprint() {
+        assert_eq!(normalize_fingerprint("SHA256:abc+/def"), "abc+/def");
+        assert_eq!(normalize_fingerprint(" abc+/def= "), "abc+/def");
+    }
+
+    #[test]
+    fn test_known_hosts_entry_name() {
+        assert_eq!(known_hosts_entry_name("example.com", 22), "example.com");
+        assert_eq!(
+            known_hosts_entry_name("localhost", 2022),
+            "[localhost]:2022"
+        );
+    }
+
+    #[test]
+    fn test_other_key_type_is_not_a_changed_key() {
+        const ED25519_KEY: &str =
+            "AAAAC3NzaC1lZDI1NTE5AAAAIOMqqnkVzrm0SdG6UOoqKLsabgH5C9okWi0dh2l9GKJl";
+        const ECDSA_KEY: &str = "AAAAE2VjZHNhLXNoYTItbmlzdHAyNTYAAAAIbmlzdHAyNTYAAABBBEmKSENjQEezOmxkZMy7opKgwFB9nkt5YRrYMjNuG5N87uRgg6CLrbo5wAdT/y6v0mKV0U2w0WZ2YB/++Tpockg=";
+        let dir = tempfile::tempdir().unwrap();
+        let known_hosts_path = dir.path().join("known_hosts");
+        std::fs::write(
+            &known_hosts_path,
+            format!("[localhost]:2022 ssh-ed25519 {}\n", ED25519_KEY),
+        )
+        .unwrap();
+
+        let session = Session::new().unwrap();
+        let mut known_hosts = session.known_hosts().unwrap();
+        known_hosts
+            .read_file(&known_hosts_path, KnownHostFileKind::OpenSSH)
+            .unwrap();
+        assert_eq!(
+            known_key_types(&known_hosts_path, "localhost", 2022),
+            vec!["ssh-ed25519"]
+        );
+
+        let ecdsa_key = STANDARD.decode(ECDSA_KEY).unwrap();
+        let result = check_known_host(
+            &known_hosts,
+            &known_hosts_path,
+            "localhost",
+            2022,
+            &ecdsa_key,
+            HostKeyType::Ecdsa256,
+        );
+        assert!(matches!(result, CheckResult::NotFound));
+
+        // an ed25519 key which is not the recorded one is still a changed key
+        let result = check_known_host(
+            &known_hosts,
+            &known_hosts_path,
+            "localhost",
+            2022,
+            &ecdsa_key,
+            HostKeyType::Ed25519,
+        );
+        assert!(matches!(result, CheckResult::Mismatch));
+    }
+
+    #[test]
+    fn test_hashed_known_hosts_name() {
+        // `ssh-keygen -H` style entry for "example.com"
+        let salt = [7u8; 20];
+        let hash = hmac_sha1(&salt, b"example.com");
+        let field = format!("|1|{}|{}", STANDARD.encode(salt), STANDARD.encode(hash));
+
+        assert!(matches_known_hosts_field(&field, "example.com"));
+        assert!(!matches_known_hosts_field(&field, "example.org"));
+        assert!(matches_known_hosts_field(
+            "example.org,[localhost]:2022",
+            "[localhost]:2022"
+        ));
+    }
+
+    #[test]
+    fn test_hmac_sha1() {
+        // RFC 2202, test case 2
+        assert_eq!(
+            hmac_sha1(b"Jefe", b"what do ya want for nothing?"),
+            [
+                0xef, 0xfc, 0xdf, 0x6a, 0xe5, 0xeb, 0x2f, 0xa2, 0xd2, 0x74, 0x16, 0xd5, 0xf1, 0x84,
+                0xdf, 0x9c, 0x25, 0x9a, 0x7c, 0x79
+            ]
+        );
+    }
+
+    #[test]
+    fn test_host_key_mode_from_str() {
+        assert_eq!("strict".parse(), Ok(HostKeyMode::Strict));
+        assert_eq!("accept-new".parse(), Ok(HostKeyMode::AcceptNew));
+        assert_eq!("off".parse(), Ok(HostKeyMode::Off));
+        assert!("maybe".parse::<HostKeyMode>().is_err());
+    }
+}
@@ -1,5 +1,10 @@
+pub mod connection_options;
+pub mod counting_writer;
+pub mod host_key;
 pub mod local_utils;
+pub mod posix_rename;
 pub mod sftp_client;
+pub mod ssh_config;
 
 #[cfg(test)]
 mod local_utils_test;
@@ -0,0 +1,119 @@
+use libssh2_sys as raw;
+use ssh2::{Error, ErrorCode, Session};
+use std::{
+    ffi::CString,
+    os::raw::{c_char, c_int},
+    path::Path,
+};
+
+// SAFETY: part of libssh2 (libssh2_sftp.h), but not declared by libssh2-sys.
+// The signature was checked against the libssh2 bundled with libssh2-sys 0.3.1 (LIBSSH2_VERSION "1.11.1_DEV"):
+// int libssh2_sftp_posix_rename_ex(LIBSSH2_SFTP *, const char *, size_t, const char *, size_t)
+// Check it again when libssh2-sys is updated, a mismatch is not detected by the compiler.
+extern "C" {
+    fn libssh2_sftp_posix_rename_ex(
+        sftp: *mut raw::LIBSSH2_SFTP,
+        source_filename: *const c_char,
+        source_filename_len: usize,
+        dest_filename: *const c_char,
+        dest_filename_len: usize,
+    ) -> c_int;
+}
+
+/**
+ * A second sftp channel on the same session, only used for the posix-rename@openssh.com extension.
+ * Unlike the rename of sftp protocol version 3, posix-rename replaces an existing target atomically.
+ *
+ * The ssh2 crate neither exposes this extension nor the raw handle of its Sftp,
+ * so this channel is opened via libssh2 directly.
+ */
+pub struct PosixRenameChannel {
+    session: Session,
+    raw: *mut raw::LIBSSH2_SFTP,
+}
+
+// SAFETY: libssh2 handles are not bound to the thread that created them, they only must not be used concurrently.
+// The raw handle is only used while the lock of the session is held (see Session::raw()),
+// the same way the ssh2 crate guards its own Sftp handle (which is Send, too).
+// The handle stays valid as long as this struct lives: the struct owns a clone of the session,
+// which keeps the underlying LIBSSH2_SESSION alive until the handle is shut down in drop().
+// The handle never leaves this module.
+unsafe impl Send for PosixRenameChannel {}
+
+impl PosixRenameChannel {
+    pub fn open(session: &Session) -> Result<PosixRenameChannel, Error> {
+        let mut raw_session = session.raw();
+        let raw_session: *mut raw::LIBSSH2_SESSION = &mut *raw_session;
+        // SAFETY: raw_session is valid and locked for the whole call
+        let raw = unsafe { raw::libssh2_sftp_init(raw_session) };
+        if raw.is_null() {
+            return Err(Error::last_session_error_raw(raw_session).unwrap_or_else(Error::unknown));
+        }
+        Ok(PosixRenameChannel {
+            session: session.clone(),
+            raw,
+        })
+    }
+
+    /**
+     * Renames from to to and replaces an existing file at to atomically.
+     * Returns Ok(false) if the server does not advertise the posix-rename@openssh.com extension.
+     */
+    pub fn rename(&self, from: &Path, to: &Path) -> Result<bool, Error> {
+        let from = remote_path_cstring(from)?;
+        let to = remote_path_cstring(to)?;
+
+        let mut raw_session = self.session.raw();
+        let raw_session: *mut raw::LIBSSH2_SESSION = &mut *raw_session;
+        // SAFETY: the session is locked, self.raw is valid until drop(),
+        // from and to are nul-terminated and outlive the call, the lengths exclude the nul byte
+        let rc = unsafe {
+            libssh2_sftp_posix_rename_ex(
+                self.raw,
+                from.as_ptr(),
+                from.as_bytes().len(),
+                to.as_ptr(),
+                to.as_bytes().len(),
+            )
+        };
+        match rc {
+            0 => Ok(true),
+            // libssh2 returns this sftp status code directly, without asking the server
+            raw::LIBSSH2_FX_OP_UNSUPPORTED => Ok(false),
+            raw::LIBSSH2_ERROR_SFTP_PROTOCOL => {
+                // SAFETY: the session is still locked, self.raw is valid
+                let status = unsafe { raw::libssh2_sftp_last_error(self.raw) };
+                Err(Error::from_errno(ErrorCode::SFTP(status as c_int)))
+            }
+            rc => Err(Error::from_session_error_raw(raw_session, rc)),
+        }
+    }
+}
+
+impl Drop for PosixRenameChannel {
+    fn drop(&mut self) {
+        let _raw_session = self.session.raw();
+        // SAFETY: the session is locked and still alive (self.session), self.raw is not used afterwards
+        unsafe {
+            raw::libssh2_sftp_shutdown(self.raw);
+        }
+    }
+}
+
+/**
+ * Same conversion as the ssh2 crate: remote paths always use forward slashes
+ */
+fn remote_path_cstring(path: &Path) -> Result<CString, Error> {
+    let invalid_path = || {
+        Error::new(
+            ErrorCode::Session(raw::LIBSSH2_ERROR_INVAL),
+            "invalid remote path",
+        )
+    };
+    let path = path.to_str().ok_or_else(invalid_path)?;
+    let path = match cfg!(windows) {
+        true => path.replace('\\', "/"),
+        false => path.to_string(),
+    };
+    CString::new(path).map_err(|_| invalid_path())
+}
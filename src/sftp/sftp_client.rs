@@ -1,52 +1,354 @@
+use miette::Diagnostic;
 use ssh2::{FileStat, Session, Sftp};
 use std::{
     collections::{HashMap, VecDeque},
     io::{copy, BufReader, BufWriter, Read, Write},
-    net::TcpStream,
+    net::{TcpStream, ToSocketAddrs},
     path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, UNIX_EPOCH},
+};
+use thiserror::Error;
+
+use super::{
+    connection_options::ConnectionOptions,
+    counting_writer::CountingWriter,
+    host_key::{prefer_known_host_key_types, verify_host_key, HostKeyPolicy},
+    local_utils::compute_relative_path_from_local,
+    posix_rename::PosixRenameChannel,
 };
-
-use super::local_utils::compute_relative_path_from_local;
 
 // Custom error type for SftpClient
-#[derive(Debug)]
+#[derive(Debug, Error, Diagnostic)]
 pub enum SftpClientError {
+    #[error("No password given for password based authentication")]
     MissingPassword,
+    #[error("No public key path given for key based authentication")]
     MissingPubkeyPath,
+    #[error("No private key path given for key based authentication")]
     MissingPrivatekeyPath,
+    #[error("Cannot open local file {}", .path.display())]
     OpenLocalFileError {
         path: PathBuf,
+        #[source]
         io_error: std::io::Error,
     },
+    #[error("Cannot open remote file {}", .path.display())]
     OpenRemoteFileError {
         path: PathBuf,
+        #[source]
         ssh2_error: ssh2::Error,
     },
+    #[error("Cannot close remote file {}", .path.display())]
     CloseRemoteFileError {
         path: PathBuf,
+        #[source]
         ssh2_error: ssh2::Error,
     },
-    RemotePathError {
-        msg: String,
-        path: PathBuf,
-    },
-    LocalPathError {
-        msg: String,
-        path: PathBuf,
-    },
-    SftpConnectionMissing {
-        msg: String,
-    },
+    #[error("{msg}: {}", .path.display())]
+    RemotePathError { msg: String, path: PathBuf },
+    #[error("{msg}: {}", .path.display())]
+    LocalPathError { msg: String, path: PathBuf },
+    #[error("{msg}")]
+    SftpConnectionMissing { msg: String },
+    #[error("Cannot copy {} to remote {}", .local_path.display(), .remote_path.display())]
     LocalToRemoteCopyError {
         local_path: PathBuf,
         remote_path: PathBuf,
+        #[source]
         io_error: std::io::Error,
     },
+    #[error("{msg}: {}", .path.display())]
     RemoteMkdirError {
         msg: String,
         path: PathBuf,
+        #[source]
         inner_error: ssh2::Error,
     },
+    #[error("Cannot remove remote path {}", .path.display())]
+    RemoteRemoveError {
+        path: PathBuf,
+        #[source]
+        ssh2_error: ssh2::Error,
+    },
+    #[error("Cannot rename remote path {} to {}", .from.display(), .to.display())]
+    RemoteRenameError {
+        from: PathBuf,
+        to: PathBuf,
+        #[source]
+        ssh2_error: ssh2::Error,
+    },
+    /**
+     * The fallback of rename_remote() removed the target, but the rename failed afterwards
+     */
+    #[error("Cannot rename remote path {} to {}, the previous file at {} was already removed", .from.display(), .to.display(), .to.display())]
+    #[diagnostic(
+        code(dev_uploader::remote_rename_target_removed),
+        help("The new content is still in the temp file, it is uploaded again with the next change of the file.")
+    )]
+    RemoteRenameTargetRemoved {
+        from: PathBuf,
+        to: PathBuf,
+        #[source]
+        ssh2_error: ssh2::Error,
+    },
+    #[error("Cannot set the attributes of remote path {}", .path.display())]
+    RemoteSetStatError {
+        path: PathBuf,
+        #[source]
+        ssh2_error: ssh2::Error,
+    },
+    #[error("Cannot read remote directory {}", .path.display())]
+    RemoteReadDirError {
+        path: PathBuf,
+        #[source]
+        ssh2_error: ssh2::Error,
+    },
+    #[error("Cannot resolve remote path {}", .path.display())]
+    RemoteRealpathError {
+        path: PathBuf,
+        #[source]
+        ssh2_error: ssh2::Error,
+    },
+    /**
+     * The TCP connection to the server could not be established
+     */
+    #[error("Cannot connect to {host_and_port}")]
+    #[diagnostic(
+        code(dev_uploader::tcp_connect),
+        help("Check that host and port are correct and that the server is reachable from this machine.")
+    )]
+    TcpConnectError {
+        host_and_port: String,
+        #[source]
+        io_error: std::io::Error,
+    },
+    /**
+     * A network operation did not finish within the configured timeout (see ConnectionOptions)
+     */
+    #[error("{operation} timed out after {}s", .timeout.as_secs())]
+    #[diagnostic(
+        code(dev_uploader::timeout),
+        help("The server or the network is not responding. Check the connection or increase --connect-timeout / --timeout.")
+    )]
+    Timeout {
+        operation: String,
+        timeout: Duration,
+    },
+    #[error("Cannot create ssh session")]
+    SessionCreateError {
+        #[source]
+        ssh2_error: ssh2::Error,
+    },
+    /**
+     * The TCP connection works, but the ssh handshake (key exchange) failed
+     */
+    #[error("SSH handshake with {host_and_port} failed")]
+    #[diagnostic(
+        code(dev_uploader::ssh_handshake),
+        help("Check that the server speaks ssh on this port and supports the sftp subsystem.")
+    )]
+    HandshakeError {
+        host_and_port: String,
+        #[source]
+        ssh2_error: ssh2::Error,
+    },
+    /**
+     * The server rejected the credentials (wrong password, unknown key, no agent identity accepted)
+     */
+    #[error("Authentication as '{username}' via {method} was rejected by the server")]
+    #[diagnostic(
+        code(dev_uploader::auth_rejected),
+        help("Check the username and the password, key file or ssh-agent identities.")
+    )]
+    AuthRejected {
+        username: String,
+        method: String,
+        #[source]
+        ssh2_error: Option<ssh2::Error>,
+    },
+    #[error("Cannot open ssh channel")]
+    ChannelError {
+        #[source]
+        ssh2_error: ssh2::Error,
+    },
+    #[error("Cannot start the sftp subsystem on the server")]
+    #[diagnostic(
+        code(dev_uploader::sftp_subsystem),
+        help("Check that sftp is enabled for this user on the server.")
+    )]
+    SftpSubsystemError {
+        #[source]
+        ssh2_error: ssh2::Error,
+    },
+    #[error("Cannot disconnect from the server")]
+    DisconnectError {
+        #[source]
+        ssh2_error: ssh2::Error,
+    },
+    /**
+     * The server did not send a host key during the handshake
+     */
+    #[error("The server {host} did not send a host key")]
+    HostKeyMissing { host: String },
+    /**
+     * The host is not in the known_hosts file (only in strict host key checking mode)
+     */
+    #[error("Host key for {host} is unknown ({fingerprint}) and not in {}", .known_hosts_file.display())]
+    #[diagnostic(
+        code(dev_uploader::host_key_unknown),
+        help("Verify the fingerprint and add the host to known_hosts (e.g. by connecting once with ssh), or use --host-key-checking accept-new.")
+    )]
+    HostKeyUnknown {
+        host: String,
+        fingerprint: String,
+        known_hosts_file: PathBuf,
+    },
+    /**
+     * The host is in the known_hosts file, but with a different key => possible man-in-the-middle attack!
+     */
+    #[error("Host key for {host} has CHANGED ({fingerprint}), it does not match the entry in {}", .known_hosts_file.display())]
+    #[diagnostic(
+        code(dev_uploader::host_key_changed),
+        help("Someone could be eavesdropping on you (man-in-the-middle attack), or the host key was just changed. If the change is expected, remove the old entry with 'ssh-keygen -R <host>'.")
+    )]
+    HostKeyChanged {
+        host: String,
+        fingerprint: String,
+        known_hosts_file: PathBuf,
+    },
+    /**
+     * The host key does not match the fingerprint pinned via --host-key-fingerprint
+     */
+    #[error("Host key for {host} has fingerprint {actual}, but {expected} was pinned")]
+    #[diagnostic(code(dev_uploader::host_key_fingerprint_mismatch))]
+    HostKeyFingerprintMismatch {
+        host: String,
+        expected: String,
+        actual: String,
+    },
+    #[error("{msg}: {}", .path.display())]
+    KnownHostsError { msg: String, path: PathBuf },
+}
+
+impl SftpClientError {
+    /**
+     * A stable, machine-readable name of the error variant, e.g. for the json output
+     */
+    pub fn kind(&self) -> &'static str {
+        match self {
+            SftpClientError::MissingPassword => "missing_password",
+            SftpClientError::MissingPubkeyPath => "missing_pubkey_path",
+            SftpClientError::MissingPrivatekeyPath => "missing_privatekey_path",
+            SftpClientError::OpenLocalFileError { .. } => "open_local_file_error",
+            SftpClientError::OpenRemoteFileError { .. } => "open_remote_file_error",
+            SftpClientError::CloseRemoteFileError { .. } => "close_remote_file_error",
+            SftpClientError::RemotePathError { .. } => "remote_path_error",
+            SftpClientError::LocalPathError { .. } => "local_path_error",
+            SftpClientError::SftpConnectionMissing { .. } => "sftp_connection_missing",
+            SftpClientError::LocalToRemoteCopyError { .. } => "local_to_remote_copy_error",
+            SftpClientError::RemoteMkdirError { .. } => "remote_mkdir_error",
+            SftpClientError::RemoteRemoveError { .. } => "remote_remove_error",
+            SftpClientError::RemoteRenameError { .. } => "remote_rename_error",
+            SftpClientError::RemoteRenameTargetRemoved { .. } => "remote_rename_target_removed",
+            SftpClientError::RemoteSetStatError { .. } => "remote_set_stat_error",
+            SftpClientError::RemoteReadDirError { .. } => "remote_read_dir_error",
+            SftpClientError::RemoteRealpathError { .. } => "remote_realpath_error",
+            SftpClientError::TcpConnectError { .. } => "tcp_connect_error",
+            SftpClientError::Timeout { .. } => "timeout",
+            SftpClientError::SessionCreateError { .. } => "session_create_error",
+            SftpClientError::HandshakeError { .. } => "handshake_error",
+            SftpClientError::AuthRejected { .. } => "auth_rejected",
+            SftpClientError::ChannelError { .. } => "channel_error",
+            SftpClientError::SftpSubsystemError { .. } => "sftp_subsystem_error",
+            SftpClientError::DisconnectError { .. } => "disconnect_error",
+            SftpClientError::HostKeyMissing { .. } => "host_key_missing",
+            SftpClientError::HostKeyUnknown { .. } => "host_key_unknown",
+            SftpClientError::HostKeyChanged { .. } => "host_key_changed",
+            SftpClientError::HostKeyFingerprintMismatch { .. } => "host_key_fingerprint_mismatch",
+            SftpClientError::KnownHostsError { .. } => "known_hosts_error",
+        }
+    }
+
+    /**
+     * True for errors which may go away when the same operation is tried again later
+     * (network errors, timeouts, lost connections), false for errors which need a fix first
+     * (permission denied, no such path, rejected credentials, ...)
+     */
+    pub fn is_transient(&self) -> bool {
+        match self {
+            SftpClientError::SftpConnectionMissing { .. }
+            | SftpClientError::Timeout { .. }
+            | SftpClientError::TcpConnectError { .. }
+            | SftpClientError::SessionCreateError { .. }
+            | SftpClientError::HandshakeError { .. }
+            | SftpClientError::ChannelError { .. } => true,
+            SftpClientError::OpenRemoteFileError { ssh2_error, .. }
+            | SftpClientError::CloseRemoteFileError { ssh2_error, .. }
+            | SftpClientError::RemoteRemoveError { ssh2_error, .. }
+            | SftpClientError::RemoteRenameError { ssh2_error, .. }
+            | SftpClientError::RemoteRenameTargetRemoved { ssh2_error, .. }
+            | SftpClientError::RemoteSetStatError { ssh2_error, .. }
+            | SftpClientError::RemoteReadDirError { ssh2_error, .. }
+            | SftpClientError::RemoteRealpathError { ssh2_error, .. } => {
+                is_transient_ssh2_error(ssh2_error)
+            }
+            SftpClientError::RemoteMkdirError { inner_error, .. } => {
+                is_transient_ssh2_error(inner_error)
+            }
+            SftpClientError::LocalToRemoteCopyError { io_error, .. } => {
+                is_transient_io_error(io_error)
+            }
+            _ => false,
+        }
+    }
+}
+
+/**
+ * The libssh2 error codes of a broken or stalled connection (LIBSSH2_ERROR_* and LIBSSH2_FX_* in libssh2.h)
+ */
+const TRANSIENT_SESSION_ERROR_CODES: [i32; 8] = [
+    -2,  // LIBSSH2_ERROR_BANNER_RECV
+    -7,  // LIBSSH2_ERROR_SOCKET_SEND
+    -9,  // LIBSSH2_ERROR_TIMEOUT
+    -13, // LIBSSH2_ERROR_SOCKET_DISCONNECT
+    -26, // LIBSSH2_ERROR_CHANNEL_CLOSED
+    -30, // LIBSSH2_ERROR_SOCKET_TIMEOUT
+    -37, // LIBSSH2_ERROR_EAGAIN
+    -43, // LIBSSH2_ERROR_SOCKET_RECV
+];
+const SFTP_NO_CONNECTION: i32 = 6; // LIBSSH2_FX_NO_CONNECTION
+const TRANSIENT_SFTP_ERROR_CODES: [i32; 2] = [
+    SFTP_NO_CONNECTION,
+    7, // LIBSSH2_FX_CONNECTION_LOST
+];
+
+fn is_transient_ssh2_error(error: &ssh2::Error) -> bool {
+    match error.code() {
+        ssh2::ErrorCode::Session(code) => TRANSIENT_SESSION_ERROR_CODES.contains(&code),
+        ssh2::ErrorCode::SFTP(code) => TRANSIENT_SFTP_ERROR_CODES.contains(&code),
+    }
+}
+
+/**
+ * ssh2 converts its errors into io errors while writing a remote file,
+ * only timeouts and EAGAIN keep a specific kind
+ */
+fn is_transient_io_error(error: &std::io::Error) -> bool {
+    use std::io::ErrorKind;
+    matches!(
+        error.kind(),
+        ErrorKind::TimedOut
+            | ErrorKind::WouldBlock
+            | ErrorKind::Interrupted
+            | ErrorKind::ConnectionReset
+            | ErrorKind::ConnectionAborted
+            | ErrorKind::BrokenPipe
+            | ErrorKind::UnexpectedEof
+    )
 }
 
 enum AuthMethod {
@@ -54,13 +356,20 @@ enum AuthMethod {
         password: String,
     },
     KeyBased {
-        pubkey: PathBuf,
+        /**
+         * Optional: libssh2 derives the public key from the private key if not given
+         */
+        pubkey: Option<PathBuf>,
         privatekey: PathBuf,
         /**
          * Optional: The passphrase for the ssh key
          */
         passphrase: Option<String>,
     },
+    /**
+     * Uses the identities of the running ssh-agent (via SSH_AUTH_SOCK, or pageant on windows)
+     */
+    Agent,
 }
 
 /**
@@ -83,6 +392,37 @@ pub struct SftpClient {
      */
     auth_method: AuthMethod,
 
+    /**
+     * How to verify the host key of the server, see host_key.rs
+     */
+    host_key_policy: HostKeyPolicy,
+
+    /**
+     * Timeouts and keepalive, see connection_options.rs
+     */
+    connection_options: ConnectionOptions,
+
+    /**
+     * Write uploads to a temp file and rename it over the target, see upload_file_explicit()
+     */
+    atomic_uploads: bool,
+
+    /**
+     * Skip uploads when the remote file has the same size and mtime as the local file,
+     * and set the remote mtime to the local mtime after each upload
+     */
+    skip_unchanged: bool,
+
+    /**
+     * Some => dry-run mode: write operations are only reported to this logger, see set_dry_run()
+     */
+    dry_run_logger: Option<DryRunLogger>,
+
+    /**
+     * Receives the number of bytes of every write to a remote file, see set_byte_progress()
+     */
+    byte_progress: Option<ByteProgress>,
+
     /**
      * All the runtime props in one struct (Check if this works correctly)
      */
@@ -92,7 +432,9 @@ pub struct SftpClient {
 /**
  * Inner Struct of SftpClient
  * Stores the props needed at runtime, like the ssh2 session, the tcp stream, etc.
+ * Starts empty (default) and is filled by SftpClient::connect()
  */
+#[derive(Default)]
 struct RuntimeProps {
     _tcp_stream: Option<TcpStream>,
     ssh2_session: Option<Session>,
@@ -124,6 +466,89 @@ struct RuntimeProps {
      * Special state for the dev-uploader I write
      */
     remote_dir_cache: HashMap<PathBuf, bool>,
+
+    /**
+     * Stops the keepalive thread of the current session (if keepalive is enabled)
+     */
+    keepalive_stop: Option<Arc<AtomicBool>>,
+
+    /**
+     * Opened on the first rename, see rename_remote().
+     * Some(None) = the channel could not be opened, posix-rename is not used for this session
+     */
+    posix_rename_channel: Option<Option<PosixRenameChannel>>,
+}
+
+/**
+ * The result of a successful SftpClient::upload_file_explicit() call
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UploadOutcome {
+    Uploaded,
+    /**
+     * The remote file has the same size and mtime as the local file, see SftpClient::set_skip_unchanged()
+     */
+    Skipped,
+}
+
+/**
+ * Receives a description of every planned write operation in dry-run mode, see SftpClient::set_dry_run()
+ */
+pub type DryRunLogger = Box<dyn Fn(String) + Send>;
+
+/**
+ * Receives the number of bytes written to a remote file, while the file is uploaded
+ */
+pub type ByteProgress = Box<dyn Fn(u64) + Send>;
+
+/**
+ * Suffix of the temp files written in atomic upload mode.
+ * Full temp file name: `.<file name>.<uploader name>.dev_uploader.tmp` (hidden, in the same dir as the target)
+ */
+pub const TEMP_FILE_SUFFIX: &str = ".dev_uploader.tmp";
+
+/**
+ * Buffer size of the local reader and the remote writer while uploading a file
+ */
+const UPLOAD_BUFFER_SIZE: usize = 128 * 1024; // 128KB
+
+/**
+ * The temp file path for an atomic upload of remote_path.
+ * The uploader name is part of the name, so that two connections never write the same temp file.
+ */
+fn temp_path_for(remote_path: &Path, uploader_name: &str) -> PathBuf {
+    let file_name = remote_path
+        .file_name()
+        .map(|f| f.to_string_lossy().to_string())
+        .unwrap_or_default();
+    remote_path.with_file_name(format!(
+        ".{}.{}{}",
+        file_name,
+        uploader_name.replace(['/', ' '], "_"),
+        TEMP_FILE_SUFFIX
+    ))
+}
+
+/**
+ * The mtime of a local file in seconds since the unix epoch (the precision of sftp v3 FileStat)
+ */
+fn mtime_secs(metadata: &std::fs::Metadata) -> Option<u64> {
+    metadata
+        .modified()
+        .ok()
+        .and_then(|mtime| mtime.duration_since(UNIX_EPOCH).ok())
+        .map(|duration| duration.as_secs())
+}
+
+fn is_temp_file_name(file_name: &str) -> bool {
+    file_name.starts_with('.') && file_name.ends_with(TEMP_FILE_SUFFIX)
+}
+
+/**
+ * libssh2 returns LIBSSH2_ERROR_TIMEOUT (-9), when a blocking call exceeds Session::set_timeout
+ */
+fn is_ssh2_timeout(error: &ssh2::Error) -> bool {
+    error.code() == ssh2::ErrorCode::Session(-9)
 }
 
 /**
@@ -131,7 +556,8 @@ struct RuntimeProps {
  */
 impl Drop for SftpClient {
     fn drop(&mut self) {
-        self.close();
+        // errors cannot be handled on drop, the connection is gone either way
+        let _ = self.close();
     }
 }
 
@@ -148,22 +574,11 @@ impl SftpClient {
         host: &str,
         port: u16,
         username: &str,
-        pubkey: PathBuf,
+        pubkey: Option<PathBuf>,
         privatekey: PathBuf,
         passphrase: Option<String>,
     ) -> Self {
         // generate runtime props
-        let runtime_props = RuntimeProps {
-            _tcp_stream: None,
-            ssh2_session: None,
-            command_channel: None,
-            file_channel: None,
-            sftp_connection: None,
-            remote_cwd: None,
-            is_closed: false,
-            remote_dir_cache: HashMap::new(),
-        };
-
         // create the SftpClient instance and validate pubkey and privatekey availability
         let sftp_client = SftpClient {
             auth_method: AuthMethod::KeyBased {
@@ -171,11 +586,17 @@ impl SftpClient {
                 privatekey,
                 passphrase,
             },
+            host_key_policy: HostKeyPolicy::default(),
+            connection_options: ConnectionOptions::default(),
+            atomic_uploads: false,
+            skip_unchanged: false,
+            dry_run_logger: None,
+            byte_progress: None,
             uploader_name: String::from(uploader_name),
             host: String::from(host),
             port,
             username: String::from(username),
-            runtime_props,
+            runtime_props: RuntimeProps::default(),
         };
 
         sftp_client
@@ -192,26 +613,42 @@ impl SftpClient {
         password: &str,
     ) -> Self {
         // generate runtime props
-        let runtime_props = RuntimeProps {
-            _tcp_stream: None,
-            ssh2_session: None,
-            command_channel: None,
-            file_channel: None,
-            sftp_connection: None,
-            remote_cwd: None,
-            is_closed: false,
-            remote_dir_cache: HashMap::new(),
-        };
-
         SftpClient {
             auth_method: AuthMethod::PasswordBased {
                 password: String::from(password),
             },
+            host_key_policy: HostKeyPolicy::default(),
+            connection_options: ConnectionOptions::default(),
+            atomic_uploads: false,
+            skip_unchanged: false,
+            dry_run_logger: None,
+            byte_progress: None,
+            uploader_name: String::from(uploader_name),
+            host: String::from(host),
+            port,
+            username: String::from(username),
+            runtime_props: RuntimeProps::default(),
+        }
+    }
+
+    /**
+     * Init a new SftpClient which authenticates with the identities of the running ssh-agent
+     */
+    pub fn with_agent(uploader_name: &str, host: &str, port: u16, username: &str) -> Self {
+        // generate runtime props
+        SftpClient {
+            auth_method: AuthMethod::Agent,
+            host_key_policy: HostKeyPolicy::default(),
+            connection_options: ConnectionOptions::default(),
+            atomic_uploads: false,
+            skip_unchanged: false,
+            dry_run_logger: None,
+            byte_progress: None,
             uploader_name: String::from(uploader_name),
             host: String::from(host),
             port,
             username: String::from(username),
-            runtime_props,
+            runtime_props: RuntimeProps::default(),
         }
     }
 
@@ -259,22 +696,82 @@ impl SftpClient {
         self.runtime_props.remote_cwd = Some(remote_cwd);
     }
 
+    /**
+     * Must be set before calling SftpClient::connect(), default: accept-new with ~/.ssh/known_hosts
+     */
+    pub fn set_host_key_policy(&mut self, host_key_policy: HostKeyPolicy) {
+        self.host_key_policy = host_key_policy;
+    }
+
+    /**
+     * Must be set before calling SftpClient::connect(), default: see ConnectionOptions::default()
+     */
+    pub fn set_connection_options(&mut self, connection_options: ConnectionOptions) {
+        self.connection_options = connection_options;
+    }
+
+    /**
+     * Enables atomic uploads (temp file + rename), default: false
+     */
+    pub fn set_atomic_uploads(&mut self, atomic_uploads: bool) {
+        self.atomic_uploads = atomic_uploads;
+    }
+
+    /**
+     * Enables skipping of unchanged files (same size and mtime on the remote), default: false
+     */
+    pub fn set_skip_unchanged(&mut self, skip_unchanged: bool) {
+        self.skip_unchanged = skip_unchanged;
+    }
+
+    /**
+     * Enables the dry-run mode, if a logger is given (default: None).
+     * In dry-run mode, all writing operations (mkdir, upload, remove, rename, setstat) only send a description
+     * of what they would do to the logger and return Ok.
+     * Reading operations (stat, readdir, realpath) still run against the server, so that the plan is accurate.
+     */
+    pub fn set_dry_run(&mut self, dry_run_logger: Option<DryRunLogger>) {
+        self.dry_run_logger = dry_run_logger;
+    }
+
+    /**
+     * Reports the uploaded bytes while a file is written (default: None).
+     * Note: the bytes of failed uploads are reported as well.
+     */
+    pub fn set_byte_progress(&mut self, byte_progress: Option<ByteProgress>) {
+        self.byte_progress = byte_progress;
+    }
+
+    pub fn is_dry_run(&self) -> bool {
+        self.dry_run_logger.is_some()
+    }
+
+    /**
+     * Returns true and reports the operation, if the dry-run mode is enabled
+     */
+    fn plan_dry_run(&self, operation: String) -> bool {
+        match self.dry_run_logger.as_ref() {
+            Some(log) => {
+                log(format!("[dry-run] {}", operation));
+                true
+            }
+            None => false,
+        }
+    }
+
     // -----------------------
     // Functions on SftpClient
     // -----------------------
 
-    pub fn connect(&mut self) -> () {
+    pub fn connect(&mut self) -> Result<(), SftpClientError> {
         let host_and_port = format!("{}:{}", self.host, self.port);
 
         // STEP 1: create underlying TcpStream with timeout
-        let tcp =
-            TcpStream::connect(host_and_port.as_str()).expect("Unable to connect to SSH server");
+        let tcp = self.connect_tcp(&host_and_port)?;
 
         // STEP 2: create ssh session & connect it to the tcp stream
-        let mut ssh_session = match Session::new() {
-            Ok(sess) => sess,
-            Err(_) => panic!("Failed to create SSH session"),
-        };
+        let mut ssh_session =
+            Session::new().map_err(|e| SftpClientError::SessionCreateError { ssh2_error: e })?;
         ssh_session.set_tcp_stream(tcp);
         ssh_session.set_compress(true);
 
@@ -283,100 +780,287 @@ impl SftpClient {
         //     Ok(_) => {}
         //     Err(e) => panic!("Failed to set banner: {}", e),
         // };
-
-        // STEP 2.2 execute the ssh auth handshake
-        match ssh_session.handshake() {
-            Ok(_) => {
-                // STEP 3: Authenticate the session
-                // Use the user's private key for authentication or the password
-                // Replace "~/.ssh/id_rsa" with the actual path to your private key if different
-
-                match &self.auth_method {
-                    AuthMethod::PasswordBased { password } => {
-                        ssh_session
-                            .userauth_password(self.username.as_str(), password.as_str())
-                            .expect("Failed to authenticate using password");
-                    }
-                    AuthMethod::KeyBased {
-                        pubkey,
-                        privatekey,
-                        passphrase,
-                    } => {
-                        ssh_session
-                            .userauth_pubkey_file(
-                                self.username.as_str(), // Replace with your SSH username
-                                Some(pubkey.as_path()), // Public key path (can be None if using the default ~/.ssh/id_rsa.pub)
-                                privatekey.as_path(),   // Path to private key file
-                                passphrase.as_deref(), // Passphrase (if your key is not encrypted, use None)
-                            )
-                            .expect("Failed to authenticate using public key");
-                    }
+        // timeout for all blocking libssh2 calls (0 = no timeout), so that a stalled connection cannot hang forever
+        let operation_timeout = self.connection_options.operation_timeout;
+        ssh_session.set_timeout(operation_timeout.map_or(0, |t| t.as_millis() as u32));
+
+        // STEP 2.2 only negotiate host key types which can be verified against known_hosts,
+        // then execute the ssh auth handshake
+        prefer_known_host_key_types(
+            &ssh_session,
+            self.host.as_str(),
+            self.port,
+            &self.host_key_policy,
+        )
+        .map_err(|e| SftpClientError::SessionCreateError { ssh2_error: e })?;
+        ssh_session.handshake().map_err(|e| {
+            if is_ssh2_timeout(&e) {
+                self.timeout_error("SSH handshake")
+            } else {
+                SftpClientError::HandshakeError {
+                    host_and_port: host_and_port.clone(),
+                    ssh2_error: e,
                 }
             }
-            Err(e) => panic!("Failed to do ssh handshake: {}", e),
+        })?;
+
+        // STEP 2.3: Verify the host key BEFORE sending any credentials to the server
+        verify_host_key(
+            &ssh_session,
+            self.host.as_str(),
+            self.port,
+            &self.host_key_policy,
+        )?;
+
+        // STEP 3: Authenticate the session
+        // Use the user's private key, the password or the ssh-agent for authentication
+        let (method, auth_result) = match &self.auth_method {
+            AuthMethod::PasswordBased { password } => (
+                "password",
+                ssh_session.userauth_password(self.username.as_str(), password.as_str()),
+            ),
+            AuthMethod::KeyBased {
+                pubkey,
+                privatekey,
+                passphrase,
+            } => (
+                "public key",
+                ssh_session.userauth_pubkey_file(
+                    self.username.as_str(),
+                    pubkey.as_deref(), // Public key path (can be None, then it's derived from the private key)
+                    privatekey.as_path(), // Path to private key file
+                    passphrase.as_deref(), // Passphrase (if your key is not encrypted, use None)
+                ),
+            ),
+            AuthMethod::Agent => (
+                "ssh-agent",
+                Self::userauth_agent_identities(&ssh_session, self.username.as_str()),
+            ),
         };
 
-        if !ssh_session.authenticated() {
-            panic!("Authentication failed");
+        if auth_result.is_err() || !ssh_session.authenticated() {
+            return Err(SftpClientError::AuthRejected {
+                username: self.username.clone(),
+                method: String::from(method),
+                ssh2_error: auth_result.err(),
+            });
         }
 
-        // STEP 4: store authenticated session on the ssh client
-        self.runtime_props.ssh2_session = Some(ssh_session);
-
-        let channel1 = self
-            .session()
-            .as_ref()
-            .unwrap()
+        // STEP 4: create the channels and the sftp connection on the authenticated session
+        let command_channel = ssh_session
             .channel_session()
-            .expect("Failed to create SSH channel");
-        self.runtime_props.command_channel = Some(channel1);
-
-        let channel2 = self
-            .session()
-            .as_ref()
-            .unwrap()
+            .map_err(|e| SftpClientError::ChannelError { ssh2_error: e })?;
+        let file_channel = ssh_session
             .channel_session()
-            .expect("Failed to create SSH channel");
-        self.runtime_props.file_channel = Some(channel2);
+            .map_err(|e| SftpClientError::ChannelError { ssh2_error: e })?;
+        let sftp_connection = ssh_session
+            .sftp()
+            .map_err(|e| SftpClientError::SftpSubsystemError { ssh2_error: e })?;
 
-        // STEP 5 create sftp connection on the existing ssh session
-        self.set_sftp_connection(
-            self.session()
-                .as_ref()
-                .unwrap()
-                .sftp()
-                .expect("Failed to create SFTP connection"),
-        );
+        // STEP 5: store everything on the sftp client
+        self.runtime_props.posix_rename_channel = None;
+        self.runtime_props.ssh2_session = Some(ssh_session);
+        self.runtime_props.command_channel = Some(command_channel);
+        self.runtime_props.file_channel = Some(file_channel);
+        self.set_sftp_connection(sftp_connection);
+        self.runtime_props.is_closed = false;
+        self.start_keepalive();
 
         // init remote sftp vars
-        let initial_cwd = self.initial_pwd_remote();
+        let initial_cwd = self.initial_pwd_remote()?;
         self.set_remote_cwd(initial_cwd);
+
+        Ok(())
+    }
+
+    /**
+     * Connects to the first reachable address of host_and_port, with the configured connect timeout
+     */
+    fn connect_tcp(&self, host_and_port: &str) -> Result<TcpStream, SftpClientError> {
+        let to_connect_error = |e: std::io::Error| SftpClientError::TcpConnectError {
+            host_and_port: host_and_port.to_string(),
+            io_error: e,
+        };
+
+        let Some(connect_timeout) = self.connection_options.connect_timeout else {
+            return TcpStream::connect(host_and_port).map_err(to_connect_error);
+        };
+
+        let mut last_error = None;
+        for addr in host_and_port.to_socket_addrs().map_err(to_connect_error)? {
+            match TcpStream::connect_timeout(&addr, connect_timeout) {
+                Ok(tcp) => return Ok(tcp),
+                Err(e) => last_error = Some(e),
+            }
+        }
+
+        match last_error {
+            Some(e) if e.kind() == std::io::ErrorKind::TimedOut => Err(SftpClientError::Timeout {
+                operation: format!("Connecting to {}", host_and_port),
+                timeout: connect_timeout,
+            }),
+            Some(e) => Err(to_connect_error(e)),
+            None => Err(to_connect_error(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                "host name did not resolve to any address",
+            ))),
+        }
+    }
+
+    /**
+     * Starts a thread which sends ssh keepalive messages on the current session.
+     * The ssh2 Session can be cloned and used from another thread,
+     * so the keepalive does not need the SftpClient (or its mutex) while an upload is running.
+     */
+    fn start_keepalive(&mut self) {
+        self.stop_keepalive();
+
+        let (Some(interval), Some(session)) = (
+            self.connection_options.keepalive_interval,
+            self.runtime_props.ssh2_session.clone(),
+        ) else {
+            return;
+        };
+
+        session.set_keepalive(true, interval.as_secs().max(1) as u32);
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = stop.clone();
+
+        let thread = std::thread::Builder::new().name(format!("{}_keepalive", self.uploader_name));
+        let spawn_result = thread.spawn(move || {
+            while !thread_stop.load(Ordering::Relaxed) {
+                std::thread::sleep(interval);
+                if thread_stop.load(Ordering::Relaxed) || session.keepalive_send().is_err() {
+                    break;
+                }
+            }
+        });
+
+        if spawn_result.is_ok() {
+            self.runtime_props.keepalive_stop = Some(stop);
+        }
     }
 
-    pub fn exec_ssh_command(&mut self, command: &str) -> String {
-        let channel = self.runtime_props.command_channel.as_mut().unwrap();
+    fn stop_keepalive(&mut self) {
+        if let Some(stop) = self.runtime_props.keepalive_stop.take() {
+            stop.store(true, Ordering::Relaxed);
+        }
+    }
+
+    fn timeout_error(&self, operation: &str) -> SftpClientError {
+        SftpClientError::Timeout {
+            operation: operation.to_string(),
+            timeout: self
+                .connection_options
+                .operation_timeout
+                .unwrap_or_default(),
+        }
+    }
+
+    /**
+     * Drops the current (probably broken) session and connects again with the same settings.
+     * The remote cwd is kept, the remote_dir_cache is cleared,
+     * since the remote dir structure may have changed while the connection was down.
+     */
+    pub fn reconnect(&mut self) -> Result<(), SftpClientError> {
+        let remote_cwd = self.remote_cwd_as_pathbuf();
+
+        // try to say goodbye to the server, but a dead connection cannot be closed properly
+        let _ = self.close();
+        self.runtime_props.posix_rename_channel = None;
+        self.runtime_props.sftp_connection = None;
+        self.runtime_props.command_channel = None;
+        self.runtime_props.file_channel = None;
+        self.runtime_props.ssh2_session = None;
+        self.runtime_props.remote_dir_cache.clear();
+
+        self.connect()?;
+
+        if let Some(remote_cwd) = remote_cwd {
+            self.set_remote_cwd(remote_cwd);
+        }
+        Ok(())
+    }
+
+    /**
+     * Checks whether the sftp session is still usable by sending a cheap request (realpath of ".") to the server.
+     * Used after a failed operation to distinguish a broken connection from a failure of the operation itself.
+     */
+    pub fn is_connected(&self) -> bool {
+        if self.runtime_props.is_closed {
+            return false;
+        }
+        match self.sftp_connection() {
+            Ok(sftp) => sftp.realpath(Path::new(".")).is_ok(),
+            Err(_) => false,
+        }
+    }
+
+    /**
+     * Tries all identities of the ssh-agent one after another, until the server accepts one.
+     * (Session::userauth_agent only tries the first identity for some libssh2 versions,
+     * so the identities are walked manually here.)
+     */
+    fn userauth_agent_identities(session: &Session, username: &str) -> Result<(), ssh2::Error> {
+        let mut agent = session.agent()?;
+        agent.connect()?;
+        agent.list_identities()?;
+
+        let identities = agent.identities()?;
+        let mut last_error = ssh2::Error::new(
+            ssh2::ErrorCode::Session(-18), // LIBSSH2_ERROR_AUTHENTICATION_FAILED
+            "The ssh-agent has no identities",
+        );
+
+        for identity in identities.iter() {
+            match agent.userauth(username, identity) {
+                Ok(_) => {
+                    let _ = agent.disconnect();
+                    return Ok(());
+                }
+                Err(e) => last_error = e,
+            }
+        }
+
+        let _ = agent.disconnect();
+        Err(last_error)
+    }
+
+    pub fn exec_ssh_command(&mut self, command: &str) -> Result<String, SftpClientError> {
+        let channel = self.runtime_props.command_channel.as_mut().ok_or_else(|| {
+            SftpClientError::SftpConnectionMissing {
+                msg: String::from(
+                    "Command channel is missing, did you forget to call SftpClient::connect()?",
+                ),
+            }
+        })?;
 
         channel
             .exec(command)
-            .expect(&format!("{} {}", "Failed to execute command: ", command));
+            .map_err(|e| SftpClientError::ChannelError { ssh2_error: e })?;
 
         let mut output = String::new();
         channel
             .read_to_string(&mut output)
-            .expect("Failed to read command output");
+            .map_err(|_| SftpClientError::ChannelError {
+                ssh2_error: ssh2::Error::from_errno(ssh2::ErrorCode::Session(-43)), // LIBSSH2_ERROR_SOCKET_RECV
+            })?;
 
-        return output;
+        Ok(output)
     }
 
-    pub fn close(&mut self) -> () {
+    pub fn close(&mut self) -> Result<(), SftpClientError> {
+        self.stop_keepalive();
         if self.session().is_some() && !self.runtime_props.is_closed {
+            // mark as closed first, so that a failing disconnect is not retried on drop
+            self.runtime_props.is_closed = true;
             self.session()
                 .as_ref()
                 .unwrap()
                 .disconnect(None, "Bye bye", Some("en"))
-                .expect("Failed to disconnect");
-            self.runtime_props.is_closed = true;
+                .map_err(|e| SftpClientError::DisconnectError { ssh2_error: e })?;
         }
+        Ok(())
     }
 
     // SFTP Commands
@@ -392,15 +1076,13 @@ impl SftpClient {
      * so we have to keep track of it ourselves.
      * Using self.remote_cwd for that.
      */
-    pub fn initial_pwd_remote(&self) -> PathBuf {
-        let out = self
-            .sftp_connection()
-            .as_ref()
-            .unwrap()
+    pub fn initial_pwd_remote(&self) -> Result<PathBuf, SftpClientError> {
+        self.sftp_connection()?
             .realpath(Path::new("."))
-            .expect("Failed to get current working directory");
-
-        return out;
+            .map_err(|e| SftpClientError::RemoteRealpathError {
+                path: PathBuf::from("."),
+                ssh2_error: e,
+            })
     }
 
     /**
@@ -423,7 +1105,10 @@ impl SftpClient {
      */
     pub fn pwd_remote(&mut self) -> &Path {
         if self.remote_cwd().is_none() {
-            let initial_cwd = self.initial_pwd_remote();
+            // connect() already sets the remote cwd, so this only fails when called before connect()
+            let initial_cwd = self
+                .initial_pwd_remote()
+                .expect("Failed to get current working directory");
             self.set_remote_cwd(initial_cwd);
         }
 
@@ -437,8 +1122,10 @@ impl SftpClient {
     pub fn pwd_remote_with_validation(&mut self) -> Result<&Path, String> {
         let remote_cwd = self.pwd_remote().to_path_buf();
         // mutable borrow of self ends here, since this function now owns the path_buf of remote cwd
-        let conn_result = self.sftp_connection();
-        let remote_stat = conn_result.unwrap().stat(remote_cwd.as_path());
+        let remote_stat = self
+            .sftp_connection()
+            .map_err(|e| e.to_string())?
+            .stat(remote_cwd.as_path());
 
         match remote_stat {
             Ok(stat) => {
@@ -475,8 +1162,7 @@ impl SftpClient {
 
         let raw_out = match self
             .sftp_connection()
-            .as_ref()
-            .unwrap()
+            .map_err(|e| e.to_string())?
             .readdir(remote_pathbuf.as_path())
         {
             Ok(entries) => entries,
@@ -528,8 +1214,7 @@ impl SftpClient {
         // it removes ".." and "." from the path
         remote_pathbuf = match self
             .sftp_connection()
-            .as_ref()
-            .unwrap()
+            .map_err(|e| e.to_string())?
             .realpath(remote_pathbuf.as_path())
         {
             Ok(pathbuf) => pathbuf,
@@ -545,8 +1230,7 @@ impl SftpClient {
         // check if new remote path exists
         match self
             .sftp_connection()
-            .as_ref()
-            .unwrap()
+            .map_err(|e| e.to_string())?
             .stat(remote_pathbuf.as_path())
         {
             Ok(stat) => {
@@ -596,11 +1280,14 @@ impl SftpClient {
      */
     pub fn stat_remote(&mut self, path: &Path) -> Result<FileStat, ssh2::Error> {
         let remote_pathbuf = self.canonicalize_remote(path);
-        return self
-            .sftp_connection()
-            .as_ref()
-            .unwrap()
-            .stat(remote_pathbuf.as_path());
+        // e.g. after a failed reconnect => report it like a lost sftp connection
+        let sftp = self.sftp_connection().map_err(|_| {
+            ssh2::Error::new(
+                ssh2::ErrorCode::SFTP(SFTP_NO_CONNECTION),
+                "Sftp connection is missing",
+            )
+        })?;
+        sftp.stat(remote_pathbuf.as_path())
     }
 
     /**
@@ -644,38 +1331,185 @@ impl SftpClient {
      * we have to account for this manually by using self.pwd()
      * as base path for the remote filepath, which accounts for self.remote_cwd
      */
-    pub fn ensure_file_remote(&mut self, path: &Path) -> () {
+    pub fn ensure_file_remote(&mut self, path: &Path) -> Result<(), SftpClientError> {
         let pathbuf = self.canonicalize_remote(path);
         let remote_path = pathbuf.as_path();
 
         // STEP 1: create the parent directory if it does not exist
         // assume that last component of path is a file
         // cannot verify this, since linux files might not have a file extension
-        let parent_path = remote_path.parent().unwrap();
-        self.ensure_dir_remote(parent_path);
+        let parent_path = remote_path
+            .parent()
+            .ok_or_else(|| SftpClientError::RemotePathError {
+                msg: String::from("Cannot get parent directory of remote path"),
+                path: remote_path.to_path_buf(),
+            })?;
+        self.ensure_dir_remote(parent_path)?;
+        if self.plan_dry_run(format!("create {}", remote_path.display())) {
+            return Ok(());
+        }
 
         // STEP 2: create the file
-        let mut file = self
-            .sftp_connection()
-            .as_ref()
-            .unwrap()
-            .create(remote_path)
-            .expect("Failed to create remote file");
-        file.close().expect("Failed to close remote file");
+        let mut file = self.sftp_connection()?.create(remote_path).map_err(|e| {
+            SftpClientError::OpenRemoteFileError {
+                path: remote_path.to_path_buf(),
+                ssh2_error: e,
+            }
+        })?;
+        file.close()
+            .map_err(|e| SftpClientError::CloseRemoteFileError {
+                path: remote_path.to_path_buf(),
+                ssh2_error: e,
+            })
     }
 
     /**
      * WIP
      */
-    pub fn remove_file_remote(&mut self, path: &Path) -> () {
+    pub fn remove_file_remote(&mut self, path: &Path) -> Result<(), SftpClientError> {
+        let pathbuf = self.canonicalize_remote(path);
+        let remote_path = pathbuf.as_path();
+        if self.plan_dry_run(format!("delete {}", remote_path.display())) {
+            return Ok(());
+        }
+
+        self.sftp_connection()?.unlink(remote_path).map_err(|e| {
+            SftpClientError::RemoteRemoveError {
+                path: remote_path.to_path_buf(),
+                ssh2_error: e,
+            }
+        })
+    }
+
+    /**
+     * Removes an empty remote directory (fails if the directory is not empty)
+     */
+    pub fn rmdir_remote(&mut self, path: &Path) -> Result<(), SftpClientError> {
         let pathbuf = self.canonicalize_remote(path);
         let remote_path = pathbuf.as_path();
+        if self.plan_dry_run(format!("rmdir {}", remote_path.display())) {
+            return Ok(());
+        }
+
+        self.sftp_connection()?
+            .rmdir(remote_path)
+            .map_err(|e| SftpClientError::RemoteRemoveError {
+                path: remote_path.to_path_buf(),
+                ssh2_error: e,
+            })
+    }
+
+    /**
+     * Renames a remote file or dir, an existing file at the target path is replaced.
+     *
+     * Uses the posix-rename@openssh.com extension if the server supports it, which replaces the target atomically.
+     * Otherwise the plain sftp rename is used: it is atomic on servers with protocol version 5+,
+     * but servers with protocol version 3 refuse to rename onto an existing file.
+     * As last resort, the target file is removed first in this case. This fallback is not atomic:
+     * the target can be missing for a short time between the removal and the rename,
+     * and it stays missing if the rename fails afterwards (see RemoteRenameTargetRemoved).
+     */
+    pub fn rename_remote(&mut self, from: &Path, to: &Path) -> Result<(), SftpClientError> {
+        let from = self.canonicalize_remote(from);
+        let to = self.canonicalize_remote(to);
+        if self.plan_dry_run(format!("rename {} -> {}", from.display(), to.display())) {
+            return Ok(());
+        }
+        let to_rename_error = |e: ssh2::Error| SftpClientError::RemoteRenameError {
+            from: from.clone(),
+            to: to.clone(),
+            ssh2_error: e,
+        };
+
+        if self
+            .posix_rename_remote(&from, &to)
+            .map_err(to_rename_error)?
+        {
+            return Ok(());
+        }
+
+        let sftp = self.sftp_connection()?;
+        match sftp.rename(&from, &to, None) {
+            Ok(_) => Ok(()),
+            Err(e) => match sftp.stat(&to) {
+                Ok(stat) if stat.is_file() => {
+                    sftp.unlink(&to).map_err(to_rename_error)?;
+                    sftp.rename(&from, &to, None).map_err(|e| {
+                        SftpClientError::RemoteRenameTargetRemoved {
+                            from: from.clone(),
+                            to: to.clone(),
+                            ssh2_error: e,
+                        }
+                    })
+                }
+                _ => Err(to_rename_error(e)),
+            },
+        }
+    }
 
-        self.sftp_connection()
+    /**
+     * Renames via the posix-rename@openssh.com extension,
+     * returns Ok(false) if the server does not support it (the plain sftp rename must be used then)
+     */
+    fn posix_rename_remote(&mut self, from: &Path, to: &Path) -> Result<bool, ssh2::Error> {
+        if self.runtime_props.posix_rename_channel.is_none() {
+            let Some(session) = self.session() else {
+                return Ok(false);
+            };
+            self.runtime_props.posix_rename_channel = Some(PosixRenameChannel::open(session).ok());
+        }
+        match self
+            .runtime_props
+            .posix_rename_channel
             .as_ref()
-            .unwrap()
-            .unlink(remote_path)
-            .expect("Failed to remove file");
+            .and_then(|c| c.as_ref())
+        {
+            Some(channel) => channel.rename(from, to),
+            None => Ok(false),
+        }
+    }
+
+    /**
+     * Sets the modification (and access) time of a remote path, in seconds since the unix epoch
+     */
+    pub fn set_mtime_remote(&mut self, path: &Path, mtime: u64) -> Result<(), SftpClientError> {
+        let pathbuf = self.canonicalize_remote(path);
+        let remote_path = pathbuf.as_path();
+        if self.plan_dry_run(format!("set mtime of {}", remote_path.display())) {
+            return Ok(());
+        }
+
+        let stat = FileStat {
+            size: None,
+            uid: None,
+            gid: None,
+            perm: None,
+            atime: Some(mtime),
+            mtime: Some(mtime),
+        };
+        self.sftp_connection()?
+            .setstat(remote_path, stat)
+            .map_err(|e| SftpClientError::RemoteSetStatError {
+                path: remote_path.to_path_buf(),
+                ssh2_error: e,
+            })
+    }
+
+    /**
+     * True if the remote file exists and has the same size and mtime (in seconds) as the local file
+     */
+    pub fn is_unchanged_remote(&mut self, local_metadata: &std::fs::Metadata, path: &Path) -> bool {
+        let Some(local_mtime) = mtime_secs(local_metadata) else {
+            return false;
+        };
+        match self.stat_remote(path) {
+            Ok(stat) => {
+                stat.is_file()
+                    && stat.size == Some(local_metadata.len())
+                    && stat.mtime == Some(local_mtime)
+            }
+            Err(_) => false,
+        }
     }
 
     /**
@@ -687,48 +1521,87 @@ impl SftpClient {
      * Example:
      * If path is `playground/subpath`, it will delete the contents of the `subpath` directory and the `subpath` directory itself.
      */
-    pub fn rmrf_remote(&mut self, path: &Path) -> () {
+    pub fn rmrf_remote(&mut self, path: &Path) -> Result<(), SftpClientError> {
         let pathbuf = self.canonicalize_remote(path);
         let remote_path = pathbuf.as_path();
 
-        self.rmrf_remote_recursive(remote_path);
+        self.rmrf_remote_recursive(remote_path)
     }
 
     /**
      * Caution: This function expects a canonicalized remote path!
      */
-    fn rmrf_remote_recursive(&mut self, path: &Path) {
+    fn rmrf_remote_recursive(&mut self, path: &Path) -> Result<(), SftpClientError> {
         // Step 1: if path is file, delete directly
         if self.has_file_remote(path) {
-            self.remove_file_remote(path);
+            self.remove_file_remote(path)?;
             println!("Removed file: {}", path.display());
-            return;
+            return Ok(());
         }
 
         // path is dir from here on
         // STEP 2: Get all entries in the directory
-        let dir_entries = self
-            .sftp_connection()
-            .as_ref()
-            .unwrap()
-            .readdir(path)
-            .expect("Failed to read directory contents");
+        let dir_entries = self.sftp_connection()?.readdir(path).map_err(|e| {
+            SftpClientError::RemoteReadDirError {
+                path: path.to_path_buf(),
+                ssh2_error: e,
+            }
+        })?;
 
         // STEP 3: dir_entries is not empty => delete all entries first
-        if !dir_entries.is_empty() {
-            for (entry_path, _stat) in dir_entries {
-                self.rmrf_remote_recursive(&entry_path);
-            }
+        for (entry_path, _stat) in dir_entries {
+            self.rmrf_remote_recursive(&entry_path)?;
         }
 
         // STEP 4: remove empty dir
-        self.sftp_connection()
-            .as_ref()
-            .unwrap()
+        self.sftp_connection()?
             .rmdir(path)
-            .expect("Failed to remove directory");
+            .map_err(|e| SftpClientError::RemoteRemoveError {
+                path: path.to_path_buf(),
+                ssh2_error: e,
+            })?;
 
         println!("Removed directory: {}", path.display());
+        Ok(())
+    }
+
+    /**
+     * Removes leftover temp files of atomic uploads (e.g. from a crashed run) in dir and all its subdirs.
+     * Returns the removed files.
+     */
+    pub fn remove_temp_files_remote(
+        &mut self,
+        dir: &Path,
+    ) -> Result<Vec<PathBuf>, SftpClientError> {
+        let pathbuf = self.canonicalize_remote(dir);
+        let mut removed_files = vec![];
+        let mut dirs_to_scan = VecDeque::from([pathbuf]);
+
+        while let Some(dir) = dirs_to_scan.pop_front() {
+            let dir_entries = self.sftp_connection()?.readdir(&dir).map_err(|e| {
+                SftpClientError::RemoteReadDirError {
+                    path: dir.to_path_buf(),
+                    ssh2_error: e,
+                }
+            })?;
+
+            for (entry_path, stat) in dir_entries {
+                if stat.is_dir() {
+                    dirs_to_scan.push_back(entry_path);
+                    continue;
+                }
+                let is_temp_file = entry_path
+                    .file_name()
+                    .map(|f| is_temp_file_name(&f.to_string_lossy()))
+                    .unwrap_or(false);
+                if is_temp_file {
+                    self.remove_file_remote(&entry_path)?;
+                    removed_files.push(entry_path);
+                }
+            }
+        }
+
+        Ok(removed_files)
     }
 
     /**
@@ -771,12 +1644,7 @@ impl SftpClient {
             // println!("Ensuring directory: {}", working_path.display());
 
             // check if working_path exists
-            let is_dir = match self
-                .sftp_connection()
-                .as_ref()
-                .unwrap()
-                .stat(working_path.as_path())
-            {
+            let is_dir = match self.sftp_connection()?.stat(working_path.as_path()) {
                 Ok(stat) => stat.is_dir(),
                 Err(_) => false,
             };
@@ -784,13 +1652,12 @@ impl SftpClient {
             if is_dir {
                 continue;
             };
+            if self.plan_dry_run(format!("mkdir {}", working_path.display())) {
+                continue;
+            }
 
             // create the directory
-            let mkdir_result = self
-                .sftp_connection()
-                .as_ref()
-                .unwrap()
-                .mkdir(working_path.as_path(), 0o755);
+            let mkdir_result = self.sftp_connection()?.mkdir(working_path.as_path(), 0o755);
 
             // check errors on the mkdir
             if let Err(e) = mkdir_result {
@@ -835,6 +1702,14 @@ impl SftpClient {
         return Ok(());
     }
 
+    /**
+     * Forgets all cached remote dirs, must be called when remote dirs were removed
+     * (by this or another SftpClient), see ensure_dir_remote_cached()
+     */
+    pub fn clear_remote_dir_cache(&mut self) {
+        self.runtime_props.remote_dir_cache.clear();
+    }
+
     // File Upload functions
     // ----------------------
 
@@ -987,6 +1862,17 @@ impl SftpClient {
      * - create the file if it does not exist
      * - overwrites the file contents if it exists
      *
+     * Atomic mode (see set_atomic_uploads()):
+     * The file is written to a hidden temp file in the same remote dir first and then renamed over the target,
+     * so that a web server never serves a half-written file.
+     *
+     * Dry-run mode (see set_dry_run()):
+     * Only reports the upload and does not ensure the parent dir, the caller plans the mkdirs via ensure_dir_remote().
+     *
+     * Skip-unchanged mode (see set_skip_unchanged()):
+     * Returns UploadOutcome::Skipped without uploading, if the remote file has the same size and mtime as the local file.
+     * After an upload, the remote mtime is set to the local mtime.
+     *
      * Special Extension:
      * If allow_cached_ensure_remote_dir is true, use self.ensure_dir_remote_cached(),
      * which ensures each unique parent dir path only once.
@@ -999,10 +1885,7 @@ impl SftpClient {
         local_filepath: &Path,
         remote_filepath: &Path,
         allow_cached_ensure_remote_dir: bool,
-    ) -> Result<(), SftpClientError> {
-        // STEP 0: prepare vars
-        const BUFFER_SIZE: usize = 128 * 1024; // 128KB
-
+    ) -> Result<UploadOutcome, SftpClientError> {
         // Step 1: prepare local path
         let local_pathbuf =
             local_filepath
@@ -1017,13 +1900,38 @@ impl SftpClient {
         let remote_pathbuf = self.canonicalize_remote(remote_filepath);
         let remote_path = remote_pathbuf.as_path();
 
-        // STEP 2: open the local file for reading
+        // STEP 2: skip the upload if the remote file is unchanged (skip-unchanged mode)
+        let local_mtime = match self.skip_unchanged {
+            true => {
+                let local_metadata =
+                    std::fs::metadata(local_path).map_err(|e| SftpClientError::LocalPathError {
+                        msg: format!("Cannot read metadata of local file: {}", e),
+                        path: local_filepath.to_path_buf(),
+                    })?;
+                if self.is_unchanged_remote(&local_metadata, remote_path) {
+                    return Ok(UploadOutcome::Skipped);
+                }
+                mtime_secs(&local_metadata)
+            }
+            false => None,
+        };
+
+        // STEP 2.1: dry-run mode => the parent dirs are planned by ensure_dir_remote() of the caller
+        if self.plan_dry_run(format!(
+            "upload {} -> {}",
+            local_path.display(),
+            remote_path.display()
+        )) {
+            return Ok(UploadOutcome::Uploaded);
+        }
+
+        // STEP 2.2: open the local file for reading
         let src_file =
             std::fs::File::open(local_path).map_err(|e| SftpClientError::OpenLocalFileError {
                 path: remote_path.to_path_buf(),
                 io_error: e,
             })?;
-        let mut reader = BufReader::with_capacity(BUFFER_SIZE, src_file);
+        let mut reader = BufReader::with_capacity(UPLOAD_BUFFER_SIZE, src_file);
 
         // STEP 3: ensure the remote parent directory exists
         let remote_dir = match remote_path.parent() {
@@ -1041,6 +1949,53 @@ impl SftpClient {
             self.ensure_dir_remote(remote_dir)?;
         }
 
+        // STEP 4: write the file, either directly or via a temp file (atomic mode)
+        match self.atomic_uploads {
+            true => self.write_remote_file_atomic(&mut reader, local_filepath, remote_path)?,
+            false => self.write_remote_file(&mut reader, local_filepath, remote_path)?,
+        }
+
+        // STEP 8: keep the local mtime, so that the next run can skip this file.
+        // Best effort: some servers refuse setstat, the file is uploaded again next time then.
+        if let Some(mtime) = local_mtime {
+            let _ = self.set_mtime_remote(remote_path, mtime);
+        }
+
+        // reader will auto-close when it goes out of scope
+        Ok(UploadOutcome::Uploaded)
+    }
+
+    /**
+     * Writes the file to a temp file in the same remote dir and renames it over remote_path afterwards
+     */
+    fn write_remote_file_atomic(
+        &mut self,
+        reader: &mut impl Read,
+        local_filepath: &Path,
+        remote_path: &Path,
+    ) -> Result<(), SftpClientError> {
+        let temp_path = temp_path_for(remote_path, &self.uploader_name);
+        let write_result = self
+            .write_remote_file(reader, local_filepath, &temp_path)
+            .and_then(|_| self.rename_remote(&temp_path, remote_path));
+        if write_result.is_err() {
+            // best effort, a leftover temp file is removed by remove_temp_files_remote() on the next start
+            if let Ok(sftp) = self.sftp_connection() {
+                let _ = sftp.unlink(&temp_path);
+            }
+        }
+        write_result
+    }
+
+    /**
+     * Opens (and truncates) the remote file and streams the contents of reader into it
+     */
+    fn write_remote_file(
+        &mut self,
+        reader: &mut impl Read,
+        local_filepath: &Path,
+        remote_path: &Path,
+    ) -> Result<(), SftpClientError> {
         // STEP 4.1: get sftp connection, returns error if not connected
         let sftp = self.sftp_connection()?;
 
@@ -1058,30 +2013,36 @@ impl SftpClient {
                 path: remote_path.to_path_buf(),
                 ssh2_error: e,
             })?;
-
-        // 128KB buffer
-        let mut writer = BufWriter::with_capacity(BUFFER_SIZE, &mut remote_file);
+        let timeout = self.connection_options.operation_timeout;
+
+        // count the bytes which are handed to the BufWriter, to report the progress of big files
+        let byte_progress = self.byte_progress.as_ref();
+        let mut writer = CountingWriter::new(
+            BufWriter::with_capacity(UPLOAD_BUFFER_SIZE, &mut remote_file),
+            |written| {
+                if let Some(report) = byte_progress {
+                    report(written);
+                }
+            },
+        );
 
         // STEP 5: copy the contents from the local file to the remote file with std::io::copy
-        match copy(&mut reader, &mut writer) {
-            Ok(_) => {}
-            Err(e) => {
-                return Err(SftpClientError::LocalToRemoteCopyError {
-                    local_path: local_filepath.to_path_buf(),
-                    remote_path: remote_path.to_path_buf(),
-                    io_error: e,
-                });
-            }
-        };
-
-        // STEP 6: flush the writer (in case something was not written yet)
-        writer
-            .flush()
-            .map_err(|e| SftpClientError::LocalToRemoteCopyError {
+        // a stalled transfer surfaces as TimedOut io error (see ConnectionOptions::operation_timeout)
+        let to_copy_error = |e: std::io::Error| match (e.kind(), timeout) {
+            (std::io::ErrorKind::TimedOut, Some(timeout)) => SftpClientError::Timeout {
+                operation: format!("Upload of {}", local_filepath.display()),
+                timeout,
+            },
+            _ => SftpClientError::LocalToRemoteCopyError {
                 local_path: local_filepath.to_path_buf(),
                 remote_path: remote_path.to_path_buf(),
                 io_error: e,
-            })?;
+            },
+        };
+        copy(reader, &mut writer).map_err(to_copy_error)?;
+
+        // STEP 6: flush the writer (in case something was not written yet)
+        writer.flush().map_err(to_copy_error)?;
 
         drop(writer); // This will close the compat wrapper
 
@@ -1093,7 +2054,6 @@ impl SftpClient {
                 ssh2_error: e,
             })?;
 
-        // reader will auto-close when it goes out of scope
         Ok(())
     }
 
@@ -1114,7 +2074,7 @@ impl SftpClient {
         remote_dir: &Path,
         local_base: Option<&Path>,
         allow_cached_ensure_remote_dir: bool,
-    ) -> Result<(), SftpClientError> {
+    ) -> Result<UploadOutcome, SftpClientError> {
         let remote_pathbuf_result = match local_base {
             Some(local_base) => {
                 self.local_to_remote_path_with_bases(local_filepath, local_base, remote_dir)
@@ -1137,7 +2097,7 @@ impl SftpClient {
         local_filepath: &Path,
         local_base: Option<&Path>,
         allow_cached_ensure_remote_dir: bool,
-    ) -> Result<(), SftpClientError> {
+    ) -> Result<UploadOutcome, SftpClientError> {
         let remote_pathbuf_result = match local_base {
             Some(local_base) => self.local_to_remote_path_with_lbase(local_filepath, local_base),
             None => self.local_to_remote_path_with_cwds(local_filepath),
@@ -1172,7 +2132,9 @@ mod tests {
             "test",
         );
 
-        client.connect();
+        client
+            .connect()
+            .expect("Failed to connect to the test sftp server");
         return client;
     }
 
@@ -1204,6 +2166,49 @@ mod tests {
     // Test Functions
     // --------------
 
+    #[test]
+    fn test_stat_remote_after_failed_reconnect() {
+        // nothing listens on port 1 => the reconnect fails and the sftp connection stays missing
+        let mut client =
+            SftpClient::with_password("dev_uploader - Unit Test", "127.0.0.1", 1, "test", "test");
+        assert!(client.reconnect().is_err());
+
+        let stat_error = client
+            .stat_remote(Path::new("/var/www/index.html"))
+            .unwrap_err();
+        assert!(matches!(
+            stat_error.code(),
+            ssh2::ErrorCode::SFTP(SFTP_NO_CONNECTION)
+        ));
+        assert!(!client.has_file_remote(Path::new("/var/www/index.html")));
+        assert!(client.ls_remote(Some("/var/www")).is_err());
+    }
+
+    #[test]
+    fn test_error_is_transient() {
+        let connection_lost = SftpClientError::OpenRemoteFileError {
+            path: PathBuf::from("/var/www/app.js"),
+            ssh2_error: ssh2::Error::new(ssh2::ErrorCode::SFTP(7), "connection lost"),
+        };
+        let permission_denied = SftpClientError::OpenRemoteFileError {
+            path: PathBuf::from("/var/www/app.js"),
+            ssh2_error: ssh2::Error::new(ssh2::ErrorCode::SFTP(3), "permission denied"),
+        };
+        let timeout = SftpClientError::Timeout {
+            operation: "Upload".to_string(),
+            timeout: Duration::from_secs(30),
+        };
+
+        assert!(connection_lost.is_transient());
+        assert!(!permission_denied.is_transient());
+        assert!(timeout.is_transient());
+        assert!(!SftpClientError::RemotePathError {
+            msg: "Remote path is not a directory".to_string(),
+            path: PathBuf::from("/var/www"),
+        }
+        .is_transient());
+    }
+
     // TODO: This test runs through but the file does not appear in the mounted volume
     #[test]
     fn test_sftp_open_file_raw() {
@@ -1241,7 +2246,20 @@ mod tests {
         test_file.close().expect("Failed to close file!");
 
         // CLEANUP
-        client.rmrf_remote(&file_path);
+        client.rmrf_remote(&file_path).unwrap();
         assert!(client.has_file_remote(file_path.as_path()) == false);
     }
+
+    #[test]
+    fn test_temp_path_for() {
+        let temp_path = temp_path_for(Path::new("/var/www/dist/main.js"), "sftp_1");
+        assert_eq!(
+            temp_path,
+            PathBuf::from("/var/www/dist/.main.js.sftp_1.dev_uploader.tmp")
+        );
+        assert!(is_temp_file_name(
+            &temp_path.file_name().unwrap().to_string_lossy()
+        ));
+        assert!(!is_temp_file_name("main.js"));
+    }
 }
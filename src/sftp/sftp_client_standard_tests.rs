@@ -24,7 +24,9 @@ fn init_sftp_client() -> SftpClient {
         "test",
     );
 
-    client.connect();
+    client
+        .connect()
+        .expect("Failed to connect to the test sftp server");
     return client;
 }
 
@@ -72,7 +74,7 @@ fn test_init_sftp_client_fixture() {
 //     let mut fixture = TEST_FIXTURE.lock().unwrap();
 //     let client = &mut fixture.client;
 
-//     let output = client.exec_ssh_command("whoami");
+//     let output = client.exec_ssh_command("whoami").unwrap();
 //     assert_eq!(output.trim(), "test");
 
 //     // channel.wait_close().unwrap();
@@ -92,7 +94,7 @@ fn test_sftp_pwd_with_validation() {
     assert_eq!(out2.is_err(), true);
 
     // CLEANUP
-    let initial_cwd = client.initial_pwd_remote();
+    let initial_cwd = client.initial_pwd_remote().unwrap();
     client.set_remote_cwd(initial_cwd);
 }
 
@@ -254,7 +256,9 @@ fn test_sftp_upload_file_explicit_remote() {
     assert!(client.has_file_remote(remote_path));
 
     // Cleanup
-    client.rmrf_remote(Path::new("/explicit_remote_dir"));
+    client
+        .rmrf_remote(Path::new("/explicit_remote_dir"))
+        .unwrap();
 }
 
 #[test]
@@ -283,7 +287,7 @@ fn test_sftp_upload_file_implicit_remote() {
 
     // CLEANUP
     let parent_dir = remote_path.parent().unwrap();
-    client.rmrf_remote(parent_dir);
+    client.rmrf_remote(parent_dir).unwrap();
 }
 
 #[test]
@@ -320,7 +324,7 @@ fn test_sftp_upload_file_implicit_different_cwds_remote() {
     client
         .cd_remote("..")
         .expect("Failed to cd back to parent directory");
-    client.rmrf_remote(new_remote_cwd);
+    client.rmrf_remote(new_remote_cwd).unwrap();
 }
 
 #[test]
@@ -358,13 +362,13 @@ fn test_sftp_ensure_and_remove_file_remote() {
     let file_path = Path::new("testfiles/depth1/depth2/ensured_remote_file.txt");
 
     // STEP 1: create the file
-    client.ensure_file_remote(file_path);
+    client.ensure_file_remote(file_path).unwrap();
 
     // Assert 1: check if the file exists
     assert!(client.has_file_remote(file_path));
 
     // STEP 2: remove the file
-    client.remove_file_remote(file_path);
+    client.remove_file_remote(file_path).unwrap();
 
     // Assert 2: check if the file is removed
     assert!(client.has_file_remote(file_path) == false);
@@ -377,7 +381,7 @@ fn test_sftp_ensure_and_remove_file_remote() {
 
     // CLEANUP: remove full path after test
     let remove_path = Path::new("testfiles");
-    client.rmrf_remote(remove_path);
+    client.rmrf_remote(remove_path).unwrap();
 
     // Assert 3: check if the full path is removed
     assert!(client.has_dir_remote(remove_path).unwrap_or(false) == false);
@@ -405,7 +409,7 @@ fn test_sftp_ensure_and_remove_dir_remote_absolute() {
 
     // TEST part 2: remove dirs
     let parent_dir = dir1_path.parent().unwrap();
-    client.rmrf_remote(parent_dir);
+    client.rmrf_remote(parent_dir).unwrap();
 
     // Assert 3: check if dirs are removed
     assert!(client.has_dir_remote(parent_dir).unwrap_or(false) == false);
@@ -432,7 +436,7 @@ fn test_sftp_ensure_and_remove_dir_remote_relative() {
 
     // TEST part 2: remove dirs
     let parent_dir = dir1_path.parent().unwrap();
-    client.rmrf_remote(parent_dir);
+    client.rmrf_remote(parent_dir).unwrap();
 
     // Assert 3: check if dirs are removed
     assert!(client.has_dir_remote(parent_dir).unwrap_or(false) == false);
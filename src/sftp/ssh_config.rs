@@ -0,0 +1,410 @@
+use std::path::{Path, PathBuf};
+
+/**
+ * The values of an OpenSSH client config (~/.ssh/config) which are relevant for the dev_uploader.
+ * All values are optional, since the config may not contain a matching Host block.
+ */
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct SshHostConfig {
+    pub host_name: Option<String>,
+    pub user: Option<String>,
+    pub port: Option<u16>,
+    pub identity_files: Vec<PathBuf>,
+}
+
+/**
+ * One `Host` block of the ssh config.
+ * Lines before the first `Host` keyword are stored in a block with the pattern `*`.
+ */
+#[derive(Debug)]
+struct HostBlock {
+    patterns: Vec<String>,
+    /**
+     * (lowercase keyword, value) pairs in file order
+     */
+    options: Vec<(String, String)>,
+}
+
+/**
+ * A parsed OpenSSH client config file, including all `Include`d files.
+ *
+ * Supported: Host blocks with wildcards (`*`, `?`) and negations (`!pattern`), Include,
+ * and the keywords HostName, User, Port and IdentityFile.
+ * Not supported: `Match` blocks, they are skipped completely.
+ */
+#[derive(Debug, Default)]
+pub struct SshConfig {
+    blocks: Vec<HostBlock>,
+}
+
+impl SshConfig {
+    /**
+     * The default location of the user's ssh config file: ~/.ssh/config
+     */
+    pub fn default_path() -> Option<PathBuf> {
+        home::home_dir().map(|home| home.join(".ssh").join("config"))
+    }
+
+    /**
+     * Reads an ssh config file.
+     * Relative Include paths are resolved against ~/.ssh, like OpenSSH does for user config files.
+     */
+    pub fn load(path: &Path) -> Result<SshConfig, std::io::Error> {
+        let content = std::fs::read_to_string(path)?;
+        let include_base = home::home_dir()
+            .map(|home| home.join(".ssh"))
+            .unwrap_or_else(|| path.parent().unwrap_or(Path::new(".")).to_path_buf());
+
+        Ok(SshConfig::parse(&content, &include_base))
+    }
+
+    pub fn parse(content: &str, include_base: &Path) -> SshConfig {
+        let mut config = SshConfig::default();
+        config.blocks.push(HostBlock {
+            patterns: vec![String::from("*")],
+            options: vec![],
+        });
+        config.parse_into(content, include_base, 0);
+        config
+    }
+
+    fn parse_into(&mut self, content: &str, include_base: &Path, depth: u8) {
+        // same recursion limit as OpenSSH
+        const MAX_INCLUDE_DEPTH: u8 = 16;
+
+        for line in content.lines() {
+            let Some((keyword, value)) = split_config_line(line) else {
+                continue;
+            };
+
+            match keyword.as_str() {
+                "host" => self.blocks.push(HostBlock {
+                    patterns: value.split_whitespace().map(String::from).collect(),
+                    options: vec![],
+                }),
+                // Match blocks are not supported => use a pattern which never matches
+                "match" => self.blocks.push(HostBlock {
+                    patterns: vec![],
+                    options: vec![],
+                }),
+                "include" => {
+                    if depth >= MAX_INCLUDE_DEPTH {
+                        continue;
+                    }
+                    // the included lines belong to the enclosing Host block,
+                    // and after the include, the enclosing block continues
+                    let enclosing_patterns = self.blocks.last().unwrap().patterns.clone();
+                    for include_path in value.split_whitespace() {
+                        for file in expand_include(include_path, include_base) {
+                            if let Ok(included) = std::fs::read_to_string(&file) {
+                                self.parse_into(&included, include_base, depth + 1);
+                            }
+                        }
+                    }
+                    self.blocks.push(HostBlock {
+                        patterns: enclosing_patterns,
+                        options: vec![],
+                    });
+                }
+                _ => self
+                    .blocks
+                    .last_mut()
+                    .unwrap()
+                    .options
+                    .push((keyword, value)),
+            }
+        }
+    }
+
+    /**
+     * Resolves the config for a host alias (as given via `--host`).
+     * Like OpenSSH, the first value found for a keyword wins,
+     * only IdentityFile values are accumulated.
+     */
+    pub fn query(&self, host: &str) -> SshHostConfig {
+        let mut result = SshHostConfig::default();
+        // expanded after all blocks are read, since %r and %h may be set by a later User / HostName line
+        let mut raw_identity_files = vec![];
+
+        let matching_blocks = self
+            .blocks
+            .iter()
+            .filter(|block| host_matches_patterns(host, &block.patterns));
+
+        for block in matching_blocks {
+            for (keyword, value) in block.options.iter() {
+                match keyword.as_str() {
+                    "hostname" if result.host_name.is_none() => {
+                        result.host_name = Some(value.replace("%h", host));
+                    }
+                    "user" if result.user.is_none() => {
+                        result.user = Some(value.clone());
+                    }
+                    "port" if result.port.is_none() => {
+                        result.port = value.parse().ok();
+                    }
+                    "identityfile" => raw_identity_files.push(value),
+                    _ => {}
+                }
+            }
+        }
+
+        let remote_host = result.host_name.as_deref().unwrap_or(host);
+        result.identity_files = raw_identity_files
+            .into_iter()
+            .map(|value| expand_identity_file(value, remote_host, result.user.as_deref()))
+            .collect();
+        result
+    }
+}
+
+/**
+ * Splits a config line into a lowercase keyword and its value.
+ * Supports `Keyword value`, `Keyword=value` and quoted values.
+ * Returns None for empty lines and comments.
+ */
+fn split_config_line(line: &str) -> Option<(String, String)> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+
+    let split_at = line.find(|c: char| c.is_whitespace() || c == '=')?;
+    let (keyword, rest) = line.split_at(split_at);
+    let value = rest
+        .trim_start()
+        .strip_prefix('=')
+        .unwrap_or(rest)
+        .trim()
+        .trim_matches('"');
+
+    Some((keyword.to_lowercase(), value.to_string()))
+}
+
+/**
+ * A host matches a Host block, if it matches at least one pattern and none of the negated patterns
+ */
+fn host_matches_patterns(host: &str, patterns: &[String]) -> bool {
+    let mut matched = false;
+
+    for pattern in patterns {
+        match pattern.strip_prefix('!') {
+            Some(negated) => {
+                if wildcard_match(negated, host) {
+                    return false;
+                }
+            }
+            None => {
+                if wildcard_match(pattern, host) {
+                    matched = true;
+                }
+            }
+        }
+    }
+
+    matched
+}
+
+/**
+ * Matches ssh config patterns: `*` matches any number of chars, `?` exactly one char.
+ * Host matching is case insensitive.
+ */
+pub fn wildcard_match(pattern: &str, text: &str) -> bool {
+    let pattern = pattern.to_lowercase().chars().collect::<Vec<_>>();
+    let text = text.to_lowercase().chars().collect::<Vec<_>>();
+
+    // iterative matching with backtracking to the last '*'
+    let (mut p, mut t) = (0, 0);
+    let mut last_star: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            last_star = Some((p, t));
+            p += 1;
+        } else if let Some((star_p, star_t)) = last_star {
+            p = star_p + 1;
+            t = star_t + 1;
+            last_star = Some((star_p, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+fn expand_tilde(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), home::home_dir()) {
+        (Some(rest), Some(home)) => home.join(rest),
+        _ => PathBuf::from(path),
+    }
+}
+
+/**
+ * Expands ~ and the tokens %d (home dir), %h (remote host name), %r (remote user) and %% in an IdentityFile value
+ */
+fn expand_identity_file(value: &str, host: &str, user: Option<&str>) -> PathBuf {
+    let home = home::home_dir()
+        .map(|h| h.to_string_lossy().to_string())
+        .unwrap_or_default();
+
+    let expanded = value
+        .replace("%%", "\u{0}")
+        .replace("%d", &home)
+        .replace("%h", host)
+        .replace("%r", user.unwrap_or(""))
+        .replace('\u{0}', "%");
+
+    expand_tilde(&expanded)
+}
+
+/**
+ * Resolves an Include argument to a list of files.
+ * Supports the wildcards `*` and `?` in the file name component (e.g. to include all files of a conf.d dir).
+ */
+fn expand_include(include_path: &str, include_base: &Path) -> Vec<PathBuf> {
+    let mut path = expand_tilde(include_path);
+    if path.is_relative() {
+        path = include_base.join(path);
+    }
+
+    let file_name = path
+        .file_name()
+        .map(|f| f.to_string_lossy().to_string())
+        .unwrap_or_default();
+
+    if !file_name.contains(['*', '?']) {
+        return vec![path];
+    }
+
+    let Some(dir) = path.parent() else {
+        return vec![];
+    };
+    let mut files = match std::fs::read_dir(dir) {
+        Ok(entries) => entries
+            .filter_map(|e| e.ok())
+            .map(|e| e.path())
+            .filter(|p| p.is_file())
+            .filter(|p| {
+                p.file_name()
+                    .map(|f| wildcard_match(&file_name, &f.to_string_lossy()))
+                    .unwrap_or(false)
+            })
+            .collect::<Vec<_>>(),
+        Err(_) => vec![],
+    };
+    // OpenSSH includes glob results in lexical order
+    files.sort();
+    files
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLE_CONFIG: &str = "
+# comment
+Host staging
+    HostName staging.example.com
+    User deploy
+    IdentityFile /keys/staging_ed25519
+
+Host *.internal !db.internal
+    User internal-user
+    Port 2222
+
+Host=prod
+    HostName=\"%h.example.com\"
+
+Host *
+    User fallback
+    Port 22
+    IdentityFile /keys/id_ed25519
+";
+
+    #[test]
+    fn test_wildcard_match() {
+        assert!(wildcard_match("*", "anything"));
+        assert!(wildcard_match("*.internal", "web.internal"));
+        assert!(wildcard_match("web?", "WEB1"));
+        assert!(!wildcard_match("web?", "web12"));
+        assert!(!wildcard_match("*.internal", "internal"));
+    }
+
+    #[test]
+    fn test_query_first_value_wins() {
+        let config = SshConfig::parse(EXAMPLE_CONFIG, Path::new("/nonexistent"));
+        let staging = config.query("staging");
+
+        assert_eq!(staging.host_name.as_deref(), Some("staging.example.com"));
+        assert_eq!(staging.user.as_deref(), Some("deploy"));
+        assert_eq!(staging.port, Some(22));
+        assert_eq!(
+            staging.identity_files,
+            vec![
+                PathBuf::from("/keys/staging_ed25519"),
+                PathBuf::from("/keys/id_ed25519")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_query_wildcards_and_negation() {
+        let config = SshConfig::parse(EXAMPLE_CONFIG, Path::new("/nonexistent"));
+
+        let web = config.query("web.internal");
+        assert_eq!(web.user.as_deref(), Some("internal-user"));
+        assert_eq!(web.port, Some(2222));
+        assert_eq!(web.host_name, None);
+
+        let db = config.query("db.internal");
+        assert_eq!(db.user.as_deref(), Some("fallback"));
+        assert_eq!(db.port, Some(22));
+
+        let prod = config.query("prod");
+        assert_eq!(prod.host_name.as_deref(), Some("prod.example.com"));
+    }
+
+    #[test]
+    fn test_identity_file_tokens_use_later_user() {
+        let config = SshConfig::parse(
+            "Host staging\n  IdentityFile ~/.ssh/%r_key\n  IdentityFile /keys/%h.pem\n  User deploy\n  HostName staging.example.com\n",
+            Path::new("/nonexistent"),
+        );
+
+        assert_eq!(
+            config.query("staging").identity_files,
+            vec![
+                home::home_dir().unwrap().join(".ssh").join("deploy_key"),
+                PathBuf::from("/keys/staging.example.com.pem")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_include() {
+        let temp_dir = tempfile::Builder::new()
+            .prefix("dev_uploader_ssh_config_test")
+            .tempdir()
+            .unwrap();
+        let include_dir = temp_dir.path().to_path_buf();
+        std::fs::create_dir_all(include_dir.join("config.d")).unwrap();
+        std::fs::write(
+            include_dir.join("config.d/10-myalias"),
+            "Host myalias\n  HostName 10.0.0.5\n  Port 2022\n",
+        )
+        .unwrap();
+
+        let config = SshConfig::parse(
+            "Include config.d/*\nHost *\n  User fallback\n",
+            &include_dir,
+        );
+        let myalias = config.query("myalias");
+        assert_eq!(myalias.host_name.as_deref(), Some("10.0.0.5"));
+        assert_eq!(myalias.port, Some(2022));
+        assert_eq!(myalias.user.as_deref(), Some("fallback"));
+    }
+}
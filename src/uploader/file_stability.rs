@@ -0,0 +1,87 @@
+use std::{
+    path::Path,
+    time::{Duration, Instant, SystemTime},
+};
+
+/**
+ * Files which are still being written after this time are uploaded anyway (and re-queued if they change during the upload)
+ */
+const MAX_STABLE_WAIT: Duration = Duration::from_secs(60);
+
+/**
+ * The size and mtime of a local file, used to detect files which are still being written
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileStamp {
+    pub size: u64,
+    pub mtime: Option<SystemTime>,
+}
+
+impl FileStamp {
+    pub fn read(path: &Path) -> std::io::Result<FileStamp> {
+        let metadata = std::fs::metadata(path)?;
+        Ok(FileStamp {
+            size: metadata.len(),
+            mtime: metadata.modified().ok(),
+        })
+    }
+
+    /**
+     * True if the file was not modified within the interval (a missing mtime counts as not stable)
+     */
+    fn is_older_than(&self, interval: Duration) -> bool {
+        self.mtime
+            .and_then(|mtime| mtime.elapsed().ok())
+            .is_some_and(|age| age >= interval)
+    }
+}
+
+/**
+ * Re-stats the file until its size and mtime did not change for the interval.
+ * Files which were not modified within the interval are returned directly.
+ *
+ * Returns the stamp of the stable file, which can be compared after the upload to detect changes during the upload.
+ */
+pub fn wait_until_stable(path: &Path, interval: Duration) -> std::io::Result<FileStamp> {
+    let started = Instant::now();
+    let mut last_stamp = FileStamp::read(path)?;
+    if last_stamp.is_older_than(interval) {
+        return Ok(last_stamp);
+    }
+
+    loop {
+        std::thread::sleep(interval);
+        let stamp = FileStamp::read(path)?;
+        if stamp == last_stamp || started.elapsed() >= MAX_STABLE_WAIT {
+            return Ok(stamp);
+        }
+        last_stamp = stamp;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wait_until_stable_waits_for_writer() {
+        let temp_dir = tempfile::Builder::new()
+            .prefix("dev_uploader_file_stability_test")
+            .tempdir()
+            .unwrap();
+        let dir = temp_dir.path().to_path_buf();
+        let file = dir.join("artifact.zip");
+        std::fs::write(&file, "part 1").unwrap();
+
+        let writer_file = file.clone();
+        let writer = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(50));
+            std::fs::write(&writer_file, "part 1 + part 2").unwrap();
+        });
+
+        let stamp = wait_until_stable(&file, Duration::from_millis(200)).unwrap();
+        writer.join().unwrap();
+        assert_eq!(stamp.size, "part 1 + part 2".len() as u64);
+        assert_eq!(stamp, FileStamp::read(&file).unwrap());
+    }
+}
@@ -1,6 +1,10 @@
 // new: upload_actor
+pub mod file_stability;
 pub mod upload_actor;
 pub mod upload_actor_handle;
+pub mod upload_event;
+pub mod upload_manifest;
+pub mod upload_queue;
 
 // new: progress_actor
 pub mod progress_actor;
@@ -1,13 +1,67 @@
-use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
-use std::sync::mpsc::Receiver as StdReceiver;
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
+use std::{io::Write, sync::mpsc::Receiver as StdReceiver};
+
+use super::upload_event::{EventWriter, UploadEvent};
+
+/**
+ * How much is printed, set with --quiet and --verbose
+ */
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd, Default)]
+pub enum Verbosity {
+    /**
+     * Only errors and the result of each batch
+     */
+    Quiet,
+    #[default]
+    Normal,
+    /**
+     * Additionally the settings on startup, every remote dir and one line per file (also with progressbars)
+     */
+    Verbose,
+}
+
+/**
+ * How the progress of the uploads is shown
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct OutputOptions {
+    pub verbosity: Verbosity,
+    /**
+     * true: draw progressbars (interactive terminals only),
+     * false: plain line based log with one line per file, for CI logs and pipes
+     */
+    pub progress_bars: bool,
+    /**
+     * Print the text output to stderr, because stdout is used for the json events
+     */
+    pub text_to_stderr: bool,
+}
 
 pub enum ProgressActorMessage {
-    AddBar {
+    /**
+     * Adds a bar which counts bytes and shows the throughput and ETA.
+     * All byte bars together make up the total bar (see AddTotalBar).
+     */
+    AddByteBar {
         name: String,
         length: u64,
         // Sends back the internal index of the added progress bar
         response_tx: oneshot::Sender<usize>,
     },
+    /**
+     * Adds a byte bar whose position is the sum of the positions of all other byte bars
+     */
+    AddTotalBar {
+        name: String,
+        response_tx: oneshot::Sender<usize>,
+    },
+    /**
+     * Advances a byte bar by the given number of bytes (and therefore the total bar)
+     */
+    IncBarBytes {
+        index: usize,
+        bytes: u64,
+    },
     SetBarLength {
         index: usize,
         length: u64,
@@ -16,10 +70,6 @@ pub enum ProgressActorMessage {
         index: usize,
         pos: u64,
     },
-    IncBarPos {
-        index: usize,
-        inc: u64,
-    },
     SetBarMsg {
         index: usize,
         msg: String,
@@ -31,33 +81,79 @@ pub enum ProgressActorMessage {
     ResetBarElapsed {
         index: usize,
     },
+    /**
+     * Always printed (errors and batch results)
+     */
     PrintLn(String),
+    /**
+     * Only printed if the verbosity is at least the given level
+     */
+    Log {
+        level: Verbosity,
+        msg: String,
+    },
+    /**
+     * The result of one file of the connection at index,
+     * printed as line in plain mode and in verbose mode
+     */
+    LogFile {
+        index: usize,
+        msg: String,
+    },
+    /**
+     * Written as json line to the event writer (if any), see --output json
+     */
+    Event(UploadEvent),
 }
 
 pub struct ProgressActor {
     // Meta for actor
     msg_rx: StdReceiver<ProgressActorMessage>,
+    output: OutputOptions,
+    event_writer: Option<EventWriter>,
 
     // Actor internal state
     mulitprogress_controller: MultiProgress,
     default_style: ProgressStyle,
+    byte_style: ProgressStyle,
     bars: Vec<ProgressBar>,
+    /**
+     * The indexes of the byte bars and of the total bar (if any)
+     */
+    byte_bars: Vec<usize>,
+    total_bar: Option<usize>,
 }
 
 impl ProgressActor {
-    pub fn new(msg_rx: StdReceiver<ProgressActorMessage>) -> Self {
-        let mulitprogress_controller = MultiProgress::new();
+    pub fn new(
+        msg_rx: StdReceiver<ProgressActorMessage>,
+        output: OutputOptions,
+        event_writer: Option<EventWriter>,
+    ) -> Self {
+        // without progressbars, the bars are still tracked but never drawn
+        let mulitprogress_controller = match output.progress_bars {
+            true => MultiProgress::new(),
+            false => MultiProgress::with_draw_target(ProgressDrawTarget::hidden()),
+        };
         let bars = vec![];
 
         let style = ProgressStyle::default_bar()
             .template("{prefix} [{elapsed_precise}] [{bar:40.cyan/blue}] {pos}/{len} {wide_msg} ")
             .expect("Error creating progress bar style!");
+        let byte_style = ProgressStyle::default_bar()
+            .template("{prefix} [{elapsed_precise}] [{bar:40.cyan/blue}] {bytes}/{total_bytes} {bytes_per_sec} ETA {eta} {wide_msg} ")
+            .expect("Error creating progress bar style!");
 
         Self {
             msg_rx,
+            output,
+            event_writer,
             mulitprogress_controller,
             default_style: style,
+            byte_style,
             bars,
+            byte_bars: vec![],
+            total_bar: None,
         }
     }
 
@@ -70,23 +166,33 @@ impl ProgressActor {
         // While loop ends when the sender part (aka msg_tx) is dropped
         while let Ok(msg) = self.msg_rx.recv() {
             match msg {
-                ProgressActorMessage::AddBar {
+                ProgressActorMessage::AddByteBar {
                     name,
                     length,
                     response_tx,
                 } => {
                     let new_index = self.actor_add_bar(name, length);
+                    self.bars[new_index].set_style(self.byte_style.clone());
+                    self.byte_bars.push(new_index);
+                    response_tx.send(new_index).unwrap();
+                }
+                ProgressActorMessage::AddTotalBar { name, response_tx } => {
+                    let new_index = self.actor_add_bar(name, 0);
+                    self.bars[new_index].set_style(self.byte_style.clone());
+                    self.total_bar = Some(new_index);
                     response_tx.send(new_index).unwrap();
                 }
+                ProgressActorMessage::IncBarBytes { index, bytes } => {
+                    self.bars[index].inc(bytes);
+                    self.actor_update_total_bar(index);
+                }
                 ProgressActorMessage::SetBarPos { index, pos } => {
-                    self.bars[index].set_position(pos)
+                    self.bars[index].set_position(pos);
+                    self.actor_update_total_bar(index);
                 }
                 ProgressActorMessage::SetBarLength { index, length } => {
                     self.bars[index].set_length(length);
                 }
-                ProgressActorMessage::IncBarPos { index, inc } => {
-                    self.bars[index].inc(inc);
-                }
                 ProgressActorMessage::SetBarMsg { index, msg } => {
                     self.bars[index].set_message(msg);
                 }
@@ -96,15 +202,76 @@ impl ProgressActor {
                 ProgressActorMessage::ResetBarElapsed { index } => {
                     self.bars[index].reset_elapsed();
                 }
-                ProgressActorMessage::PrintLn(msg) => {
-                    if let Err(e) = self.mulitprogress_controller.println(msg) {
-                        eprintln!("Error printing message via MultiProgress class: {:?}", e);
+                ProgressActorMessage::PrintLn(msg) => self.actor_println(msg),
+                ProgressActorMessage::Log { level, msg } => {
+                    if level <= self.output.verbosity {
+                        self.actor_println(msg);
+                    }
+                }
+                ProgressActorMessage::LogFile { index, msg } => {
+                    let show_file_lines = match self.output.progress_bars {
+                        true => self.output.verbosity == Verbosity::Verbose,
+                        false => self.output.verbosity >= Verbosity::Normal,
+                    };
+                    if show_file_lines {
+                        let msg = format!("[{}] {}", self.bars[index].prefix(), msg);
+                        self.actor_println(msg);
                     }
                 }
+                ProgressActorMessage::Event(event) => self.actor_write_event(event),
             }
         }
     }
 
+    fn actor_println(&self, msg: String) {
+        // MultiProgress::println() does nothing with a hidden draw target
+        if !self.output.progress_bars {
+            match self.output.text_to_stderr {
+                true => eprintln!("{}", msg),
+                false => println!("{}", msg),
+            }
+            return;
+        }
+        if let Err(e) = self.mulitprogress_controller.println(msg) {
+            eprintln!("Error printing message via MultiProgress class: {:?}", e);
+        }
+    }
+
+    /**
+     * Writes one json line per event, flushed directly so that consumers can react while the batch is running
+     */
+    fn actor_write_event(&mut self, event: UploadEvent) {
+        let Some(event_writer) = self.event_writer.as_mut() else {
+            return;
+        };
+        let write_result = event
+            .to_json_line()
+            .map_err(std::io::Error::other)
+            .and_then(|line| writeln!(event_writer, "{}", line))
+            .and_then(|_| event_writer.flush());
+        if let Err(e) = write_result {
+            eprintln!("Error writing json event: {:?}", e);
+        }
+    }
+
+    /**
+     * Syncs the total bar with the byte bars, after the byte bar at index changed
+     */
+    fn actor_update_total_bar(&mut self, index: usize) {
+        let Some(total_index) = self.total_bar else {
+            return;
+        };
+        if !self.byte_bars.contains(&index) {
+            return;
+        }
+        let total_pos = self
+            .byte_bars
+            .iter()
+            .map(|byte_index| self.bars[*byte_index].position())
+            .sum();
+        self.bars[total_index].set_position(total_pos);
+    }
+
     fn actor_add_bar(&mut self, name: String, length: u64) -> usize {
         let pb = self
             .mulitprogress_controller
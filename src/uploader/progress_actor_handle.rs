@@ -1,6 +1,9 @@
 use std::sync::mpsc::{channel as std_channel, SendError as StdSendError, Sender as StdSender};
 
-use super::progress_actor::{ProgressActor, ProgressActorMessage};
+use super::{
+    progress_actor::{OutputOptions, ProgressActor, ProgressActorMessage, Verbosity},
+    upload_event::{EventWriter, UploadEvent},
+};
 
 #[derive(Clone)]
 pub struct ProgressActorHandle {
@@ -8,11 +11,11 @@ pub struct ProgressActorHandle {
 }
 
 impl ProgressActorHandle {
-    pub fn new() -> Self {
+    pub fn new(output: OutputOptions, event_writer: Option<EventWriter>) -> Self {
         let (msg_tx, msg_rx) = std_channel();
 
         // Create the actor and pass the channel receiver (rx)
-        let actor = ProgressActor::new(msg_rx);
+        let actor = ProgressActor::new(msg_rx, output, event_writer);
 
         // spawn the actor
         let thread = std::thread::Builder::new().name("progress_actor_main".to_string());
@@ -23,9 +26,9 @@ impl ProgressActorHandle {
         Self { msg_tx }
     }
 
-    pub fn add_bar(&mut self, name: String, length: u64) -> Result<usize, oneshot::RecvError> {
+    pub fn add_byte_bar(&mut self, name: String, length: u64) -> Result<usize, oneshot::RecvError> {
         let (response_tx, response_rx) = oneshot::channel();
-        let msg = ProgressActorMessage::AddBar {
+        let msg = ProgressActorMessage::AddByteBar {
             name,
             length,
             response_tx,
@@ -39,6 +42,25 @@ impl ProgressActorHandle {
         response_rx.recv()
     }
 
+    pub fn add_total_bar(&mut self, name: String) -> Result<usize, oneshot::RecvError> {
+        let (response_tx, response_rx) = oneshot::channel();
+        let msg = ProgressActorMessage::AddTotalBar { name, response_tx };
+        let _ = self.msg_tx.send(msg);
+        response_rx.recv()
+    }
+
+    /**
+     * Takes &self (like print_ln), so that it can be called from a SftpClient::ByteProgress callback
+     */
+    pub fn inc_bar_bytes(
+        &self,
+        index: usize,
+        bytes: u64,
+    ) -> Result<(), StdSendError<ProgressActorMessage>> {
+        let msg = ProgressActorMessage::IncBarBytes { index, bytes };
+        self.msg_tx.send(msg)
+    }
+
     pub fn set_bar_pos(
         &mut self,
         index: usize,
@@ -57,15 +79,6 @@ impl ProgressActorHandle {
         self.msg_tx.send(msg)
     }
 
-    pub fn inc_bar_pos(
-        &mut self,
-        index: usize,
-        inc: u64,
-    ) -> Result<(), StdSendError<ProgressActorMessage>> {
-        let msg = ProgressActorMessage::IncBarPos { index, inc };
-        self.msg_tx.send(msg)
-    }
-
     pub fn set_bar_msg(
         &mut self,
         index: usize,
@@ -96,4 +109,33 @@ impl ProgressActorHandle {
         let msg = ProgressActorMessage::PrintLn(msg);
         self.msg_tx.send(msg)
     }
+
+    /**
+     * Prints the message only if the verbosity is at least the given level
+     */
+    pub fn log(
+        &self,
+        level: Verbosity,
+        msg: String,
+    ) -> Result<(), StdSendError<ProgressActorMessage>> {
+        let msg = ProgressActorMessage::Log { level, msg };
+        self.msg_tx.send(msg)
+    }
+
+    /**
+     * Sends a json event (ignored without --output json)
+     */
+    pub fn emit(&self, event: UploadEvent) -> Result<(), StdSendError<ProgressActorMessage>> {
+        let msg = ProgressActorMessage::Event(event);
+        self.msg_tx.send(msg)
+    }
+
+    pub fn log_file(
+        &self,
+        index: usize,
+        msg: String,
+    ) -> Result<(), StdSendError<ProgressActorMessage>> {
+        let msg = ProgressActorMessage::LogFile { index, msg };
+        self.msg_tx.send(msg)
+    }
 }
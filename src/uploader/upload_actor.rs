@@ -1,19 +1,49 @@
-use super::progress_actor_handle::ProgressActorHandle;
-use crate::{sftp::sftp_client::SftpClient, utils::split_to_n_chunks};
+use super::{
+    file_stability::{wait_until_stable, FileStamp},
+    progress_actor::{OutputOptions, Verbosity},
+    progress_actor_handle::ProgressActorHandle,
+    upload_event::{EventWriter, UploadEvent},
+    upload_manifest::{ManifestEntry, UploadManifest},
+    upload_queue::{QueueOrder, UploadQueue, MAX_REQUEUES, MAX_RETRIES},
+};
+use crate::{
+    sftp::{
+        connection_options::ConnectionOptions,
+        host_key::HostKeyPolicy,
+        local_utils::compute_relative_path_from_local,
+        sftp_client::{ByteProgress, DryRunLogger, SftpClient, SftpClientError, UploadOutcome},
+        ssh_config::wildcard_match,
+    },
+    utils::backoff_delay,
+};
 use chrono::Local;
-use core::sync;
+use indicatif::HumanBytes;
 use std::{
     collections::HashSet,
     path::{Path, PathBuf},
-    sync::{mpsc::Receiver as StdReceiver, Arc, Mutex},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc::Receiver as StdReceiver,
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
 };
 
+/**
+ * Reconnect settings for a dropped sftp session:
+ * waits 1s, 2s, 4s, ... (max 60s) between the attempts, gives up after RECONNECT_MAX_ATTEMPTS
+ */
+const RECONNECT_MAX_ATTEMPTS: u32 = 10;
+const RECONNECT_BASE_DELAY: Duration = Duration::from_secs(1);
+const RECONNECT_MAX_DELAY: Duration = Duration::from_secs(60);
+
 pub struct UploadActor {
     // Meta for actor
     pub msg_rx: StdReceiver<UploadActorMessage>,
 
     // Static info - set on creation
     connection_count: u8,
+    upload_options: UploadOptions,
 
     // Level 1 - work in main thead of the actor
     // ---------------------------------------------------
@@ -24,6 +54,14 @@ pub struct UploadActor {
     connections: Vec<Arc<Mutex<SftpClient>>>,
     // This handle is cloneable, so that multiple threads can access it
     progress_handler: ProgressActorHandle,
+    /**
+     * The index of the progressbar for the bytes of the whole batch (below the bars of the connections)
+     */
+    total_bar: usize,
+    /**
+     * Content hashes of the uploaded files, shared by all upload threads (see --manifest)
+     */
+    manifest: Option<Arc<Mutex<UploadManifest>>>,
 }
 
 pub enum UploadActorMessage {
@@ -37,28 +75,259 @@ pub enum UploadActorMessage {
          */
         local_base_dir: Option<PathBuf>,
     },
+    /**
+     * Same as UploadFiles, but sends back the counts of the batch when it is finished
+     */
+    PushFiles {
+        files: Vec<PathBuf>,
+        remote_dir: Option<PathBuf>,
+        local_base_dir: Option<PathBuf>,
+        response_tx: oneshot::Sender<UploadCounts>,
+    },
+    /**
+     * Removes the remote files of locally deleted files (see --sync-deletes),
+     * remote_dir and local_base_dir are the same as for UploadFiles
+     */
+    RemoveFiles {
+        files: Vec<PathBuf>,
+        remote_dir: Option<PathBuf>,
+        local_base_dir: Option<PathBuf>,
+    },
+    /**
+     * Renames remote files or dirs after a local rename: (old local path, new local path),
+     * remote_dir and local_base_dir are the same as for UploadFiles
+     */
+    RenamePaths {
+        renames: Vec<(PathBuf, PathBuf)>,
+        remote_dir: Option<PathBuf>,
+        local_base_dir: Option<PathBuf>,
+    },
+    /**
+     * Removes leftover temp files of atomic uploads inside the remote dir (see --atomic)
+     */
+    CleanupTempFiles { remote_dir: Option<PathBuf> },
 }
 
 #[derive(Clone)]
 pub enum AuthMethod {
     Password(String),
     /**
-     * pubkey: PathBuf,
+     * pubkey: Option<PathBuf>,
      * privatekey: PathBuf,
      * passphrase: Option<String>,
      */
-    Pubkey(PathBuf, PathBuf, Option<String>),
+    Pubkey(Option<PathBuf>, PathBuf, Option<String>),
+    /**
+     * Uses the identities of the running ssh-agent
+     */
+    Agent,
+}
+
+/**
+ * Options for how the files of a batch are uploaded
+ */
+#[derive(Debug, Clone, Default)]
+pub struct UploadOptions {
+    pub queue_order: QueueOrder,
+    /**
+     * After removing a remote file, also remove its parent dirs when they are empty now
+     * (up to the remote target dir of the upload pair, which is never removed)
+     */
+    pub prune_empty_dirs: bool,
+    /**
+     * Paths (relative to the upload pair, wildcards `*` and `?` allowed) which are never removed on the remote
+     */
+    pub protected_paths: Vec<String>,
+    /**
+     * Upload into a hidden temp file and rename it over the target afterwards,
+     * see SftpClient::upload_file_explicit()
+     */
+    pub atomic_uploads: bool,
+    /**
+     * Skip files whose remote file has the same size and mtime, see SftpClient::set_skip_unchanged()
+     */
+    pub skip_unchanged: bool,
+    /**
+     * Only print the planned remote operations, see SftpClient::set_dry_run()
+     */
+    pub dry_run: bool,
+    /**
+     * Wait until size and mtime of a file did not change for this interval before uploading it,
+     * and upload it again if it changed during the upload. None = upload directly.
+     */
+    pub stable_wait: Option<Duration>,
+    /**
+     * Progressbars or plain log lines and the verbosity
+     */
+    pub output: OutputOptions,
+}
+
+impl UploadOptions {
+    fn is_protected(&self, relative_path: &Path) -> bool {
+        let relative_str = relative_path.to_string_lossy();
+        self.protected_paths.iter().any(|pattern| {
+            relative_path.starts_with(pattern) || wildcard_match(pattern, &relative_str)
+        })
+    }
+
+    /**
+     * A rename moves everything below the old path away => it must not be a protected path
+     * and must not contain one (e.g. renaming `public` would move the protected `public/uploads`)
+     */
+    fn is_protected_rename_source(&self, relative_from: &Path) -> bool {
+        self.is_protected(relative_from)
+            || self
+                .protected_paths
+                .iter()
+                .any(|pattern| Path::new(pattern).starts_with(relative_from))
+    }
+}
+
+/**
+ * Counts the outcomes of the files of one upload worker or a whole batch
+ */
+#[derive(Debug, Clone, Default)]
+pub struct UploadCounts {
+    pub uploaded: u64,
+    /**
+     * Unchanged files, see UploadOptions::skip_unchanged
+     */
+    pub skipped: u64,
+    pub failed: u64,
+    /**
+     * The size of the uploaded files
+     */
+    pub bytes: u64,
+    pub failed_files: Vec<FailedFile>,
+}
+
+/**
+ * A file which could not be uploaded, listed at the end of the batch summary
+ */
+#[derive(Debug, Clone)]
+pub struct FailedFile {
+    pub file: PathBuf,
+    pub error: String,
+}
+
+impl UploadCounts {
+    pub fn add(&mut self, other: &UploadCounts) {
+        self.uploaded += other.uploaded;
+        self.skipped += other.skipped;
+        self.failed += other.failed;
+        self.bytes += other.bytes;
+        self.failed_files.extend(other.failed_files.iter().cloned());
+    }
+
+    fn add_failed(&mut self, file: &Path, error: String) {
+        self.failed += 1;
+        self.failed_files.push(FailedFile {
+            file: file.to_path_buf(),
+            error,
+        });
+    }
+
+    /**
+     * The summary printed after a batch: counts, bytes, wall time and throughput,
+     * followed by one line per failed file
+     */
+    pub fn summary(&self, wall_time: Duration) -> String {
+        let seconds = wall_time.as_secs_f64();
+        let throughput = match seconds > 0.0 {
+            true => (self.bytes as f64 / seconds) as u64,
+            false => self.bytes,
+        };
+        let mut summary = format!(
+            "Batch finished: {} - {} in {:.1}s ({}/s)",
+            self,
+            HumanBytes(self.bytes),
+            seconds,
+            HumanBytes(throughput)
+        );
+        if !self.failed_files.is_empty() {
+            summary.push_str("\nFailed files:");
+            for failed_file in &self.failed_files {
+                summary.push_str(&format!(
+                    "\n  - {}: {}",
+                    failed_file.file.display(),
+                    failed_file.error
+                ));
+            }
+        }
+        summary
+    }
+}
+
+impl std::fmt::Display for UploadCounts {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} uploaded, {} skipped unchanged, {} failed",
+            self.uploaded, self.skipped, self.failed
+        )
+    }
+}
+
+/**
+ * Everything needed to open one sftp connection, shared by all connections of the UploadActor
+ */
+#[derive(Clone)]
+pub struct ConnectionConfig {
+    pub host: String,
+    pub port: u16,
+    pub username: String,
+    pub auth_method: AuthMethod,
+    pub host_key_policy: HostKeyPolicy,
+    pub connection_options: ConnectionOptions,
+}
+
+impl ConnectionConfig {
+    /**
+     * Creates a new (not yet connected) SftpClient with these settings
+     */
+    pub fn create_client(&self, client_name: &str) -> SftpClient {
+        let mut client = match &self.auth_method {
+            // Create SftpClient instance with password auth
+            AuthMethod::Password(password) => SftpClient::with_password(
+                client_name,
+                self.host.as_str(),
+                self.port,
+                self.username.as_str(),
+                password.as_str(),
+            ),
+            // Create SftpClient instance with pubkey auth
+            AuthMethod::Pubkey(pubkey, privatekey, passphrase) => SftpClient::new(
+                client_name,
+                self.host.as_str(),
+                self.port,
+                self.username.as_str(),
+                pubkey.clone(),
+                privatekey.clone(),
+                passphrase.clone(),
+            ),
+            // Create SftpClient instance with ssh-agent auth
+            AuthMethod::Agent => SftpClient::with_agent(
+                client_name,
+                self.host.as_str(),
+                self.port,
+                self.username.as_str(),
+            ),
+        };
+        client.set_host_key_policy(self.host_key_policy.clone());
+        client.set_connection_options(self.connection_options.clone());
+        client
+    }
 }
 
 impl UploadActor {
     pub fn new(
         rx: StdReceiver<UploadActorMessage>,
         count: u8,
-        host: String,
-        port: u16,
-        username: String,
-        auth_method: AuthMethod,
-    ) -> Self {
+        connection_config: ConnectionConfig,
+        upload_options: UploadOptions,
+        manifest: Option<UploadManifest>,
+        event_writer: Option<EventWriter>,
+    ) -> Result<Self, SftpClientError> {
         // Step 1: Validate count
         if count == 0 {
             panic!("Connection count must be 1 at minimum!");
@@ -72,89 +341,79 @@ impl UploadActor {
         }
 
         // Step 3: init progress actor and internally start it's actor thread
-        let progress_handler = ProgressActorHandle::new();
+        let progress_handler = ProgressActorHandle::new(upload_options.output, event_writer);
 
         // loop through count, spawn a thread
         // and create the necessary instances of SftpClient and ProgressBar
         let mut tasks = vec![];
-        let connections = Arc::new(Mutex::new(Vec::new()));
         for i in 0..count {
             let client_name = client_names.get(i as usize).unwrap().clone();
+
+            // Create progressbar with ProgressActor
+            // (before spawning the thread, so that bar i always belongs to connection i)
+            // TODO: show spinner for connection progress to the sftp server + show errors if connection fails as message for progressbar!
+            let bar_index = progress_handler
+                .clone()
+                .add_byte_bar(client_name.clone(), 0)
+                .expect("Error adding progressbar to progress actor!");
+
             let thread = std::thread::Builder::new().name(client_name.clone());
 
             // thread_* vars will be moved into the thread by compiler
-            let mut thread_progress_handler = progress_handler.clone();
-            let thread_connections = connections.clone();
-            // vars for uploader instance
-            let thread_host = host.clone();
-            let thread_port = port.clone();
-            let thread_username = username.clone();
-            let thread_auth_method = auth_method.clone();
-
-            let task = thread.spawn(move || {
-                // Create progressbar with ProgressActor
-                // TODO: show spinner for connection progress to the sftp server + show errors if connection fails as message for progressbar!
-                thread_progress_handler
-                    .add_bar(client_name.clone(), 100)
-                    .expect("Error adding progressbar to progress actor!");
-
-                match thread_auth_method {
-                    AuthMethod::Password(password) => {
-                        // Create SftpClient instance with password auth
-                        let mut client = SftpClient::with_password(
-                            client_name.as_str(),
-                            thread_host.as_str(),
-                            thread_port,
-                            thread_username.as_str(),
-                            password.as_str(),
-                        );
-                        client.connect();
-                        thread_connections.lock().unwrap().push(client);
-                    }
-                    AuthMethod::Pubkey(pubkey, privatekey, passphrase) => {
-                        // Create SftpClient instance with pubkey auth
-                        let mut client = SftpClient::new(
-                            client_name.as_str(),
-                            thread_host.as_str(),
-                            thread_port,
-                            thread_username.as_str(),
-                            pubkey,
-                            privatekey,
-                            passphrase,
-                        );
-                        client.connect();
-                        thread_connections.lock().unwrap().push(client);
-                    }
+            let thread_connection_config = connection_config.clone();
+            let thread_atomic_uploads = upload_options.atomic_uploads;
+            let thread_skip_unchanged = upload_options.skip_unchanged;
+            let thread_dry_run_logger: Option<DryRunLogger> = match upload_options.dry_run {
+                true => {
+                    let progress_handler = progress_handler.clone();
+                    Some(Box::new(move |msg| {
+                        let _ = progress_handler.print_ln(msg);
+                    }))
                 }
+                false => None,
+            };
+            let thread_byte_progress: ByteProgress = {
+                let progress_handler = progress_handler.clone();
+                Box::new(move |bytes| {
+                    let _ = progress_handler.inc_bar_bytes(bar_index, bytes);
+                })
+            };
 
-                // thread_connections lock will be released due to scope end dropping the lock
+            let task = thread.spawn(move || {
+                let mut client = thread_connection_config.create_client(client_name.as_str());
+                client.set_atomic_uploads(thread_atomic_uploads);
+                client.set_skip_unchanged(thread_skip_unchanged);
+                client.set_dry_run(thread_dry_run_logger);
+                client.set_byte_progress(Some(thread_byte_progress));
+                client.connect().map(|_| client)
             });
 
             tasks.push(task.expect("Error spawning thread!"));
         }
 
+        let total_bar = progress_handler
+            .clone()
+            .add_total_bar("total".to_string())
+            .expect("Error adding progressbar to progress actor!");
+
+        // Collect the connected clients in the order of their names,
+        // the first connection error is returned (all connections use the same settings anyway)
+        let mut connections = vec![];
         for task in tasks {
-            task.join().expect("Error joining a thread!");
+            let client = task.join().expect("Error joining a thread!")?;
+            connections.push(Arc::new(Mutex::new(client)));
         }
 
-        let connections_unwrapped = match Arc::try_unwrap(connections)
-            .ok()
-            .map(|mutex| mutex.into_inner().unwrap())
-        {
-            Some(connections) => connections
-                .into_iter()
-                .map(|client| Arc::new(Mutex::new(client)))
-                .collect(),
-            None => panic!("Error unwrapping connections mutex!"),
-        };
-
-        Self {
+        Ok(Self {
             msg_rx: rx,
             connection_count: count,
+            upload_options,
             client_names,
-            connections: connections_unwrapped,
+            connections,
             progress_handler,
-        }
+            total_bar,
+            manifest: manifest.map(|m| Arc::new(Mutex::new(m))),
+        })
     }
 
     /**
@@ -171,7 +430,36 @@ impl UploadActor {
                     files,
                     remote_dir,
                     local_base_dir,
-                } => self.actor_upload_files(files, remote_dir, local_base_dir),
+                } => {
+                    self.actor_upload_files(files, remote_dir, local_base_dir);
+                }
+                // Case 1.1: Upload files and report the result back (push subcommand)
+                UploadActorMessage::PushFiles {
+                    files,
+                    remote_dir,
+                    local_base_dir,
+                    response_tx,
+                } => {
+                    let counts = self.actor_upload_files(files, remote_dir, local_base_dir);
+                    // the receiver may be gone already, nothing to do then
+                    let _ = response_tx.send(counts);
+                }
+                // Case 2: Receive locally removed files to remove on the remote
+                UploadActorMessage::RemoveFiles {
+                    files,
+                    remote_dir,
+                    local_base_dir,
+                } => self.actor_remove_files(files, remote_dir, local_base_dir),
+                // Case 3: Receive local renames to apply on the remote
+                UploadActorMessage::RenamePaths {
+                    renames,
+                    remote_dir,
+                    local_base_dir,
+                } => self.actor_rename_files(renames, remote_dir, local_base_dir),
+                // Case 4: Remove leftover temp files of crashed runs
+                UploadActorMessage::CleanupTempFiles { remote_dir } => {
+                    self.actor_cleanup_temp_files(remote_dir)
+                }
             }
         }
     }
@@ -181,10 +469,10 @@ impl UploadActor {
         files_to_upload: Vec<PathBuf>,
         target_dir: Option<PathBuf>,
         local_base_dir: Option<PathBuf>,
-    ) {
+    ) -> UploadCounts {
         // Step 1: Ignore empty upload events
         if files_to_upload.len() == 0 {
-            return;
+            return UploadCounts::default();
         }
 
         // FOR DEBUGGING
@@ -196,13 +484,18 @@ impl UploadActor {
         // self.actor_print_ln(message);
 
         // Step 2: Log the upload event
+        let batch_started = Instant::now();
+        self.actor_emit(UploadEvent::BatchDetected {
+            files: files_to_upload.len(),
+            remote_dir: target_dir.clone(),
+        });
         let upload_event_ts = Local::now();
         let msg = format!(
             "Detected Files to upload: {} - {} ",
             files_to_upload.len(),
             upload_event_ts.format("%H:%M:%S (%Y-%m-%d)").to_string(),
         );
-        self.actor_print_ln(msg);
+        self.actor_log(Verbosity::Normal, msg);
 
         // Step 3: prepare remote path tree
         // - Base Problem: If each file creates it's own parent dir path tree,
@@ -214,6 +507,25 @@ impl UploadActor {
         let mut remote_paths = HashSet::new();
         // get a sftp connection (any one)
         let mut path_tree_client = self.connections.first().unwrap().lock().unwrap();
+        if !path_tree_client.is_connected() {
+            if let Err(e) =
+                reconnect_with_backoff(&mut path_tree_client, &mut self.progress_handler.clone(), 0)
+            {
+                let mut counts = UploadCounts::default();
+                let error = format!("Error reconnecting: {}", e);
+                for file in files_to_upload.iter() {
+                    counts.add_failed(file, error.clone());
+                }
+                self.actor_print_ln(counts.summary(batch_started.elapsed()));
+                self.actor_emit(UploadEvent::BatchFinished {
+                    uploaded: 0,
+                    skipped: 0,
+                    failed: counts.failed,
+                    duration_ms: batch_started.elapsed().as_millis() as u64,
+                });
+                return counts;
+            }
+        }
 
         // Step 3.1: calculate all remote dirs
         for file in files_to_upload.iter() {
@@ -246,7 +558,10 @@ impl UploadActor {
         for path in remote_paths.iter() {
             // create the remote path tree
             // TODO: add proper progressbar for path creation
-            self.actor_print_ln(format!("Ensure remote path: {:?}", path));
+            self.actor_log(
+                Verbosity::Verbose,
+                format!("Ensure remote path: {:?}", path),
+            );
 
             match path_tree_client.ensure_dir_remote_cached(path) {
                 Ok(_) => {}
@@ -259,11 +574,15 @@ impl UploadActor {
         // Step 3.3: free reference to my sftp client for preparing the remote path tree
         drop(path_tree_client);
 
-        // Step 4: split to n chunks
-        let chunks = split_to_n_chunks(
-            files_to_upload.into_iter().collect(),
-            self.connection_count as usize,
-        );
+        // Step 4: put all files into one shared queue, idle connections pull the next file from there
+        let batch_bytes = files_to_upload
+            .iter()
+            .map(|file| local_file_size(file))
+            .sum();
+        self.progress_handler
+            .set_bar_length(self.total_bar, batch_bytes)
+            .expect("Error setting progressbar length!");
+        let queue = UploadQueue::new(files_to_upload, self.upload_options.queue_order);
 
         // Step 4.2: Reset progressbars elapsed time - BROKEN: Resets all elapsed times AFTER uploading and not before
         // for i in 0..self.connection_count {
@@ -272,43 +591,68 @@ impl UploadActor {
         //         .expect("Error resetting progressbar elapsed time!");
         // }
 
-        // Step 5: Go through each chunk and upload them
-        // create space for the thread handles BEFORE looping over the chunks
+        // Step 5: Start one upload worker per connection
+        // create space for the thread handles BEFORE looping over the connections
         let mut tasks = vec![];
-        for (i, chunk) in chunks.iter().enumerate() {
-            // Step 1 per Chunk - Set progressbar length to the number of files in the chunk
+        // workers which still pull files from the queue, see "connection lost" below
+        let active_workers = Arc::new(AtomicUsize::new(self.connection_count as usize));
+        for i in 0..self.connection_count as usize {
+            // Step 1 per Worker - Reset the progressbar,
+            // its length grows with the size of every file the worker pulls from the queue
             self.progress_handler
-                .set_bar_length(i, chunk.len() as u64)
+                .set_bar_length(i, 0)
                 .expect("Error setting progressbar length!");
+            self.progress_handler
+                .set_bar_pos(i, 0)
+                .expect("Error setting progressbar position!");
 
-            // Step 2 per Chunk - Prepare vars for thread
+            // Step 2 per Worker - Prepare vars for thread
             // all thread-* vars will be moved into the thread by compiler
             let thread_client_arc = self.connections[i].clone();
-            let thread_chunk = chunk.clone();
+            let thread_queue = queue.clone();
             let thread_target_dir = target_dir.clone();
             let thread_name = self.client_names[i].clone();
             let thread_local_base_dir = local_base_dir.clone();
             let mut thread_progress_handler = self.progress_handler.clone();
+            // the manifest is not updated in dry-run mode, but still used to plan the skipped files
+            let thread_manifest = self.manifest.clone();
+            let thread_dry_run = self.upload_options.dry_run;
+            let thread_stable_wait = self.upload_options.stable_wait;
+            let thread_active_workers = active_workers.clone();
 
-            // Step 3 per Chunk - Spawn the thread
+            // Step 3 per Worker - Spawn the thread
             let thread = std::thread::Builder::new().name(thread_name.to_string());
             let task = thread.spawn(move || {
                 let mut thread_client = thread_client_arc.lock().unwrap();
-                for file in thread_chunk {
+                let mut pulled_bytes = 0;
+                let mut done_bytes = 0;
+                let mut counts = UploadCounts::default();
+                // (error kind, error) of the failed reconnect => this connection is dead for the rest of the batch
+                let mut connection_lost: Option<(&'static str, String)> = None;
+                while let Some(file) = thread_queue.pop() {
                     // pre upload - prepare progressbar
+                    let file_size = local_file_size(&file);
+                    pulled_bytes += file_size;
+                    thread_progress_handler
+                        .set_bar_length(i, pulled_bytes)
+                        .expect("Error setting progressbar length!");
                     let msg = format!("Uploading: {:?}", file);
                     thread_progress_handler
                         .set_bar_msg(i, msg)
                         .expect("Error setting progressbar msg!");
+                    let _ = thread_progress_handler.emit(UploadEvent::FileStarted {
+                        connection: thread_name.clone(),
+                        file: file.clone(),
+                    });
 
                     // while upload
-                    let sync_result = match &thread_target_dir {
-                        None => thread_client.sync_file_to_cwd(
+                    let sync_file = |client: &mut SftpClient| match &thread_target_dir {
+                        None => client.sync_file_to_cwd(
                             file.as_path(),
                             thread_local_base_dir.as_ref().map(|p| p.as_path()),
                             true,
                         ),
-                        Some(target_dir) => thread_client.sync_file_to_dir(
+                        Some(target_dir) => client.sync_file_to_dir(
                             file.as_path(),
                             target_dir.as_path(),
                             thread_local_base_dir.as_ref().map(|p| p.as_path()),
@@ -316,44 +660,605 @@ impl UploadActor {
                         ),
                     };
 
+                    // wait until the file is completely written (e.g. big zip artifacts), before it is hashed and uploaded
+                    // Note: a missing file is not handled here, its upload reports the error
+                    let stable_stamp = match (thread_stable_wait, thread_dry_run) {
+                        (Some(interval), false) => wait_until_stable(&file, interval).ok(),
+                        _ => None,
+                    };
+
+                    // skip files which were uploaded with the same content before (manifest mode)
+                    let manifest_entry = thread_manifest.as_ref().and_then(|_| {
+                        let relative_path = compute_relative_path_from_local(
+                            &file,
+                            thread_local_base_dir.as_deref(),
+                        )
+                        .ok()?;
+                        let entry = ManifestEntry::from_file(&file).ok()?;
+                        Some((relative_path, entry))
+                    });
+                    let is_unchanged = match (&thread_manifest, &manifest_entry) {
+                        (Some(manifest), Some((relative_path, entry))) => manifest
+                            .lock()
+                            .unwrap()
+                            .is_unchanged(thread_target_dir.as_deref(), relative_path, entry),
+                        _ => false,
+                    };
+
+                    let upload_started = Instant::now();
+                    let mut sync_result = match is_unchanged {
+                        true => Ok(UploadOutcome::Skipped),
+                        false => sync_file(&mut thread_client),
+                    };
+
+                    // the upload failed because the connection dropped => reconnect and retry the file once
+                    if sync_result.is_err() && !thread_client.is_connected() {
+                        sync_result = match reconnect_with_backoff(
+                            &mut thread_client,
+                            &mut thread_progress_handler,
+                            i,
+                        ) {
+                            Ok(_) => sync_file(&mut thread_client),
+                            Err(e) => {
+                                connection_lost = Some((e.kind(), error_with_sources(&e)));
+                                Err(e)
+                            }
+                        };
+                    }
+
+                    // the file changed during the upload => the remote file may be truncated, upload it again
+                    let changed_during_upload = matches!(sync_result, Ok(UploadOutcome::Uploaded))
+                        && stable_stamp
+                            .is_some_and(|stamp| FileStamp::read(&file).ok() != Some(stamp));
+                    if changed_during_upload {
+                        if thread_queue.requeue(file.clone()) {
+                            let msg = format!("Changed during upload, queued again: {:?}", file);
+                            thread_progress_handler
+                                .set_bar_msg(i, msg)
+                                .expect("Error setting progressbar msg!");
+                            done_bytes += file_size;
+                            thread_progress_handler
+                                .set_bar_pos(i, done_bytes)
+                                .expect("Error setting progressbar position!");
+                            continue;
+                        }
+                        let msg = format!("it still changed after {} uploads", MAX_REQUEUES + 1);
+                        counts.add_failed(&file, msg.clone());
+                        let _ = thread_progress_handler
+                            .log_file(i, format!("Failed {}: {}", file.display(), msg));
+                        let _ = thread_progress_handler.emit(UploadEvent::file_failed(
+                            &thread_name,
+                            &file,
+                            "changed_during_upload",
+                            msg,
+                        ));
+                        done_bytes += file_size;
+                        thread_progress_handler
+                            .set_bar_pos(i, done_bytes)
+                            .expect("Error setting progressbar position!");
+                        continue;
+                    }
+
+                    if let (Ok(_), Some(manifest), Some((relative_path, entry)), false) = (
+                        &sync_result,
+                        &thread_manifest,
+                        manifest_entry,
+                        thread_dry_run,
+                    ) {
+                        manifest.lock().unwrap().record(
+                            thread_target_dir.as_deref(),
+                            &relative_path,
+                            entry,
+                        );
+                    }
+
                     match sync_result {
-                        Ok(_) => {}
+                        Ok(UploadOutcome::Uploaded) => {
+                            counts.uploaded += 1;
+                            counts.bytes += file_size;
+                            let duration = upload_started.elapsed();
+                            let _ = thread_progress_handler.log_file(
+                                i,
+                                format!(
+                                    "Uploaded {} ({}, {:.2}s)",
+                                    file.display(),
+                                    HumanBytes(file_size),
+                                    duration.as_secs_f64()
+                                ),
+                            );
+                            let _ = thread_progress_handler.emit(UploadEvent::FileUploaded {
+                                connection: thread_name.clone(),
+                                file: file.clone(),
+                                bytes: file_size,
+                                duration_ms: duration.as_millis() as u64,
+                            });
+                        }
+                        Ok(UploadOutcome::Skipped) => {
+                            counts.skipped += 1;
+                            let msg = format!("Skipped (unchanged): {:?}", file);
+                            let _ = thread_progress_handler.log_file(i, msg.clone());
+                            let _ = thread_progress_handler.emit(UploadEvent::FileSkipped {
+                                connection: thread_name.clone(),
+                                file: file.clone(),
+                            });
+                            thread_progress_handler
+                                .set_bar_msg(i, msg)
+                                .expect("Error setting progressbar msg!");
+                        }
                         Err(e) => {
-                            println!("Error uploading file: {:?}, {:?}", file.display(), e);
+                            let error = error_with_sources(&e);
+                            // network errors, timeouts, ... => upload the file again at the end of the batch
+                            if e.is_transient() && connection_lost.is_none() {
+                                if let Some(delay) = thread_queue.retry(file.clone()) {
+                                    let msg = format!(
+                                        "Failed {}: {} - retrying in {}s",
+                                        file.display(),
+                                        error,
+                                        delay.as_secs()
+                                    );
+                                    let _ = thread_progress_handler.log_file(i, msg.clone());
+                                    let _ =
+                                        thread_progress_handler.emit(UploadEvent::FileRetrying {
+                                            connection: thread_name.clone(),
+                                            file: file.clone(),
+                                            error_kind: e.kind().to_string(),
+                                            error: e.to_string(),
+                                            retry_in_ms: delay.as_millis() as u64,
+                                        });
+                                    thread_progress_handler
+                                        .set_bar_msg(i, msg)
+                                        .expect("Error setting progressbar msg!");
+                                    done_bytes += file_size;
+                                    thread_progress_handler
+                                        .set_bar_pos(i, done_bytes)
+                                        .expect("Error setting progressbar position!");
+                                    continue;
+                                }
+                            }
+                            let error = match e.is_transient() && connection_lost.is_none() {
+                                true => format!(
+                                    "{} (gave up after {} attempts)",
+                                    error,
+                                    MAX_RETRIES + 1
+                                ),
+                                false => error,
+                            };
+                            counts.add_failed(&file, error.clone());
+                            let _ = thread_progress_handler
+                                .log_file(i, format!("Failed {}: {}", file.display(), error));
+                            let _ = thread_progress_handler.emit(UploadEvent::file_failed(
+                                &thread_name,
+                                &file,
+                                e.kind(),
+                                e.to_string(),
+                            ));
                         }
                     };
 
-                    // after upload - inc progressbar
+                    // after upload - set the progressbar to the end of this file
+                    // (also for skipped and failed files, whose bytes were not or only partially reported)
+                    done_bytes += file_size;
                     thread_progress_handler
-                        .inc_bar_pos(i, 1)
-                        .expect("Error incrementing progressbar!");
+                        .set_bar_pos(i, done_bytes)
+                        .expect("Error setting progressbar position!");
+
+                    if connection_lost.is_some() {
+                        break;
+                    }
+                }
+
+                // connection lost: the other connections upload the remaining files,
+                // only the last active worker fails them directly (instead of a new reconnect cycle per file)
+                let other_workers_active = thread_active_workers.fetch_sub(1, Ordering::SeqCst) > 1;
+                if let (Some((error_kind, error)), false) = (&connection_lost, other_workers_active)
+                {
+                    for file in thread_queue.drain() {
+                        let error = format!("Not uploaded, the connection is lost: {}", error);
+                        counts.add_failed(&file, error.clone());
+                        let _ = thread_progress_handler
+                            .log_file(i, format!("Failed {}: {}", file.display(), error));
+                        let _ = thread_progress_handler.emit(UploadEvent::file_failed(
+                            &thread_name,
+                            &file,
+                            error_kind,
+                            error,
+                        ));
+                    }
                 }
                 // thread_client will be dropped here, releasing the lock for this specific SftpClient
                 // => does not block other threads from accessing their SftpClient
+                counts
             });
 
             tasks.push(task);
         }
 
         // Step 6: Wait for all threads to finish
+        let mut batch_counts = UploadCounts::default();
         for (i, task) in tasks.into_iter().enumerate() {
             match task {
                 Ok(task) => {
-                    if let Err(error) = task.join() {
-                        self.progress_handler
-                            .print_ln("Error joining a file upload thread!".to_string());
+                    let counts = match task.join() {
+                        Ok(counts) => counts,
+                        Err(_) => {
+                            let _ = self
+                                .progress_handler
+                                .print_ln("Error joining a file upload thread!".to_string());
+                            UploadCounts::default()
+                        }
                     };
 
                     // finish the progressbar after upload threads are done
                     self.progress_handler
-                        .finish_bar(i, "Finished uploading files!".to_string())
+                        .finish_bar(i, format!("Finished uploading files! ({})", counts))
                         .expect("Error finishing progressbar!");
+                    batch_counts.add(&counts);
                 }
                 Err(e) => {
                     println!("Error spawning thread: {:?}", e);
                 }
             }
         }
+
+        self.progress_handler
+            .finish_bar(
+                self.total_bar,
+                format!("Batch finished! ({})", batch_counts),
+            )
+            .expect("Error finishing progressbar!");
+        self.actor_print_ln(batch_counts.summary(batch_started.elapsed()));
+        self.actor_emit(UploadEvent::BatchFinished {
+            uploaded: batch_counts.uploaded,
+            skipped: batch_counts.skipped,
+            failed: batch_counts.failed,
+            duration_ms: batch_started.elapsed().as_millis() as u64,
+        });
+        self.save_manifest();
+        batch_counts
+    }
+
+    /**
+     * Removes the remote counterparts of locally removed files.
+     * Runs on the first connection only, since removing is cheap compared to uploading.
+     */
+    fn actor_remove_files(
+        &mut self,
+        files_to_remove: Vec<PathBuf>,
+        target_dir: Option<PathBuf>,
+        local_base_dir: Option<PathBuf>,
+    ) {
+        if files_to_remove.is_empty() {
+            return;
+        }
+
+        let connection = self.connections.first().unwrap().clone();
+        let mut client = connection.lock().unwrap();
+        if !client.is_connected() {
+            if let Err(e) =
+                reconnect_with_backoff(&mut client, &mut self.progress_handler.clone(), 0)
+            {
+                self.actor_print_ln(format!("Error reconnecting: {}", e));
+                return;
+            }
+        }
+
+        let mut removed_dirs = false;
+        for file in files_to_remove.iter() {
+            // the path of the file relative to the upload pair, used for the protected paths
+            let relative_path =
+                match compute_relative_path_from_local(file, local_base_dir.as_deref()) {
+                    Ok(p) => p,
+                    Err(e) => {
+                        self.actor_print_ln(format!("Error computing relative path: {}", e));
+                        continue;
+                    }
+                };
+            if self.upload_options.is_protected(&relative_path) {
+                self.actor_log(
+                    Verbosity::Normal,
+                    format!(
+                        "Not removing protected remote path: {}",
+                        relative_path.display()
+                    ),
+                );
+                continue;
+            }
+
+            let remote_path = match client.local_to_remote_path(
+                file.as_path(),
+                local_base_dir.as_deref(),
+                target_dir.as_deref(),
+            ) {
+                Ok(p) => p,
+                Err(e) => {
+                    self.actor_print_ln(format!("Error converting local to remote path: {}", e));
+                    continue;
+                }
+            };
+
+            // a locally removed dir arrives here as well, it is only removed if it is empty on the remote
+            let remove_result = match client.stat_remote(&remote_path) {
+                Ok(stat) if stat.is_dir() => {
+                    removed_dirs = true;
+                    client.rmdir_remote(&remote_path)
+                }
+                Ok(_) => client.remove_file_remote(&remote_path),
+                // already gone on the remote => nothing to do
+                Err(_) => continue,
+            };
+            match remove_result {
+                Ok(_) => {
+                    self.actor_log(
+                        Verbosity::Normal,
+                        format!("Removed remote: {}", remote_path.display()),
+                    );
+                    self.update_manifest(|manifest| {
+                        manifest.remove(target_dir.as_deref(), &relative_path)
+                    });
+                }
+                Err(e) => {
+                    self.actor_print_ln(format!("Error removing remote path: {}", e));
+                    continue;
+                }
+            }
+
+            if self.upload_options.prune_empty_dirs {
+                removed_dirs |=
+                    self.prune_empty_parent_dirs(&mut client, &remote_path, &relative_path);
+            }
+        }
+        drop(client);
+
+        // the other connections may still have the removed dirs in their remote_dir_cache
+        if removed_dirs {
+            for connection in self.connections.iter() {
+                connection.lock().unwrap().clear_remote_dir_cache();
+            }
+        }
+        self.save_manifest();
+    }
+
+    /**
+     * Renames the remote counterparts of locally renamed files or dirs, instead of uploading them again.
+     * Falls back to uploading the new path (and removing the old remote path),
+     * when the old remote path does not exist or the rename fails.
+     * Protected old paths (see UploadOptions::protected_paths) are never renamed or removed, only the new path is uploaded.
+     */
+    fn actor_rename_files(
+        &mut self,
+        renames: Vec<(PathBuf, PathBuf)>,
+        target_dir: Option<PathBuf>,
+        local_base_dir: Option<PathBuf>,
+    ) {
+        if renames.is_empty() {
+            return;
+        }
+
+        let connection = self.connections.first().unwrap().clone();
+        let mut client = connection.lock().unwrap();
+        if !client.is_connected() {
+            if let Err(e) =
+                reconnect_with_backoff(&mut client, &mut self.progress_handler.clone(), 0)
+            {
+                self.actor_print_ln(format!("Error reconnecting: {}", e));
+                return;
+            }
+        }
+
+        let mut fallback_uploads = vec![];
+        let mut renamed_dirs = false;
+        for (from, to) in renames.iter() {
+            let remote_paths = client
+                .local_to_remote_path(from, local_base_dir.as_deref(), target_dir.as_deref())
+                .and_then(|remote_from| {
+                    client
+                        .local_to_remote_path(to, local_base_dir.as_deref(), target_dir.as_deref())
+                        .map(|remote_to| (remote_from, remote_to))
+                });
+            let (remote_from, remote_to) = match remote_paths {
+                Ok(paths) => paths,
+                Err(e) => {
+                    self.actor_print_ln(format!("Error converting local to remote path: {}", e));
+                    continue;
+                }
+            };
+
+            // the manifest entries move with the renamed paths
+            let relative_paths = compute_relative_path_from_local(from, local_base_dir.as_deref())
+                .and_then(|relative_from| {
+                    compute_relative_path_from_local(to, local_base_dir.as_deref())
+                        .map(|relative_to| (relative_from, relative_to))
+                })
+                .ok();
+
+            // never move or remove a protected remote path => upload the new path only
+            let is_protected = match &relative_paths {
+                Some((relative_from, _)) => self
+                    .upload_options
+                    .is_protected_rename_source(relative_from),
+                // unknown relative path => cannot check the protected paths
+                None => !self.upload_options.protected_paths.is_empty(),
+            };
+            if is_protected {
+                self.actor_log(
+                    Verbosity::Normal,
+                    format!(
+                        "Not renaming protected remote path: {}, uploading {} instead",
+                        remote_from.display(),
+                        remote_to.display()
+                    ),
+                );
+                fallback_uploads.extend(files_in_local_path(to));
+                continue;
+            }
+
+            let remote_from_stat = client.stat_remote(&remote_from).ok();
+            // None: the old remote path is missing => nothing to rename
+            let rename_result = remote_from_stat.as_ref().map(|_| {
+                remote_to
+                    .parent()
+                    .map_or(Ok(()), |parent| client.ensure_dir_remote_cached(parent))
+                    .and_then(|_| client.rename_remote(&remote_from, &remote_to))
+            });
+
+            match rename_result {
+                Some(Ok(_)) => {
+                    self.actor_log(
+                        Verbosity::Normal,
+                        format!(
+                            "Renamed remote: {} -> {}",
+                            remote_from.display(),
+                            remote_to.display()
+                        ),
+                    );
+                    renamed_dirs |= remote_from_stat.is_some_and(|stat| stat.is_dir());
+                    if let Some((relative_from, relative_to)) = &relative_paths {
+                        self.update_manifest(|manifest| {
+                            manifest.rename(target_dir.as_deref(), relative_from, relative_to)
+                        });
+                    }
+                    // the file may have been changed after the rename, in the same batch
+                    if to.is_file()
+                        && !client.is_dry_run()
+                        && !same_size_remote(&mut client, to, &remote_to)
+                    {
+                        fallback_uploads.push(to.clone());
+                    }
+                }
+                Some(Err(e)) => {
+                    self.actor_print_ln(format!("Error renaming remote, uploading instead: {}", e));
+                    if let Some((relative_from, _)) = &relative_paths {
+                        self.update_manifest(|manifest| {
+                            manifest.remove(target_dir.as_deref(), relative_from)
+                        });
+                    }
+                    fallback_uploads.extend(files_in_local_path(to));
+                    // a file would stay on the remote with its old name otherwise
+                    if remote_from_stat.is_some_and(|stat| stat.is_file()) {
+                        if let Err(e) = client.remove_file_remote(&remote_from) {
+                            self.actor_print_ln(format!("Error removing old remote file: {}", e));
+                        }
+                    }
+                }
+                None => fallback_uploads.extend(files_in_local_path(to)),
+            }
+        }
+        drop(client);
+
+        // the other connections may still have the old dir names in their remote_dir_cache
+        if renamed_dirs {
+            for connection in self.connections.iter() {
+                connection.lock().unwrap().clear_remote_dir_cache();
+            }
+        }
+
+        self.actor_upload_files(fallback_uploads, target_dir, local_base_dir);
+    }
+
+    fn actor_cleanup_temp_files(&mut self, target_dir: Option<PathBuf>) {
+        let remote_dir = target_dir.unwrap_or_else(|| PathBuf::from("."));
+
+        let connection = self.connections.first().unwrap().clone();
+        let mut client = connection.lock().unwrap();
+        if !client.is_connected() {
+            if let Err(e) =
+                reconnect_with_backoff(&mut client, &mut self.progress_handler.clone(), 0)
+            {
+                self.actor_print_ln(format!("Error reconnecting: {}", e));
+                return;
+            }
+        }
+
+        // nothing to clean up, if the remote dir was never uploaded to
+        if !client.has_dir_remote(&remote_dir).unwrap_or(false) {
+            return;
+        }
+
+        match client.remove_temp_files_remote(&remote_dir) {
+            Ok(removed_files) => {
+                for file in removed_files {
+                    self.actor_log(
+                        Verbosity::Normal,
+                        format!("Removed leftover temp file: {}", file.display()),
+                    );
+                }
+            }
+            Err(e) => self.actor_print_ln(format!("Error removing leftover temp files: {}", e)),
+        }
+    }
+
+    /**
+     * Removes the empty parent dirs of a removed remote path, from the inside out.
+     * Stops at the first non-empty or protected dir and never removes the remote root of the upload pair.
+     * Returns true if at least one dir was removed.
+     */
+    fn prune_empty_parent_dirs(
+        &self,
+        client: &mut SftpClient,
+        remote_path: &Path,
+        relative_path: &Path,
+    ) -> bool {
+        let mut removed_any = false;
+        let mut remote_dir = remote_path.parent();
+        let mut relative_dir = relative_path.parent();
+
+        // relative_dir is empty when remote_dir is the remote root of the upload pair
+        while let (Some(dir), Some(rel_dir)) = (remote_dir, relative_dir) {
+            if rel_dir.as_os_str().is_empty() || self.upload_options.is_protected(rel_dir) {
+                break;
+            }
+
+            let is_empty = client
+                .ls_remote(Some(&dir.to_string_lossy()))
+                .map(|entries| entries.is_empty())
+                .unwrap_or(false);
+            if !is_empty || client.rmdir_remote(dir).is_err() {
+                break;
+            }
+
+            self.actor_log(
+                Verbosity::Normal,
+                format!("Removed empty remote dir: {}", dir.display()),
+            );
+            removed_any = true;
+            remote_dir = dir.parent();
+            relative_dir = rel_dir.parent();
+        }
+
+        removed_any
+    }
+
+    fn update_manifest(&self, update: impl FnOnce(&mut UploadManifest)) {
+        if self.upload_options.dry_run {
+            return;
+        }
+        if let Some(manifest) = self.manifest.as_ref() {
+            update(&mut manifest.lock().unwrap());
+        }
+    }
+
+    /**
+     * Persists the manifest after each batch, so that it survives a restart
+     */
+    fn save_manifest(&self) {
+        let Some(manifest) = self.manifest.as_ref() else {
+            return;
+        };
+        let save_result = manifest.lock().unwrap().save();
+        if let Err(e) = save_result {
+            self.actor_print_ln(format!("Error saving upload manifest: {}", e));
+        }
+    }
+
+    /**
+     * Like actor_print_ln(), but only printed if the verbosity is at least the given level
+     */
+    fn actor_log(&self, level: Verbosity, message: String) {
+        let _ = self.progress_handler.log(level, message);
+    }
+
+    fn actor_emit(&self, event: UploadEvent) {
+        let _ = self.progress_handler.emit(event);
     }
 
     fn actor_print_ln(&self, message: String) {
@@ -366,3 +1271,117 @@ impl UploadActor {
         }
     }
 }
+
+/**
+ * Reconnects a broken SftpClient with exponential backoff.
+ * Shows the reconnect state on the progressbar of the connection.
+ * Returns the last connect error, if all attempts failed.
+ */
+fn reconnect_with_backoff(
+    client: &mut SftpClient,
+    progress_handler: &mut ProgressActorHandle,
+    bar_index: usize,
+) -> Result<(), SftpClientError> {
+    let mut attempt = 0;
+    loop {
+        let delay = backoff_delay(attempt, RECONNECT_BASE_DELAY, RECONNECT_MAX_DELAY);
+        let _ = progress_handler.set_bar_msg(
+            bar_index,
+            format!(
+                "Connection lost - Reconnecting in {}s (attempt {}/{})",
+                delay.as_secs(),
+                attempt + 1,
+                RECONNECT_MAX_ATTEMPTS
+            ),
+        );
+        std::thread::sleep(delay);
+
+        match client.reconnect() {
+            Ok(_) => {
+                let _ = progress_handler.set_bar_msg(bar_index, String::from("Reconnected"));
+                return Ok(());
+            }
+            Err(e) if attempt + 1 >= RECONNECT_MAX_ATTEMPTS => {
+                let _ =
+                    progress_handler.set_bar_msg(bar_index, format!("Reconnecting failed: {}", e));
+                return Err(e);
+            }
+            Err(_) => attempt += 1,
+        }
+    }
+}
+
+/**
+ * Returns the path itself if it is a file, or all files inside the path if it is a dir
+ */
+fn files_in_local_path(path: &Path) -> Vec<PathBuf> {
+    walkdir::WalkDir::new(path)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file())
+        .map(|e| e.into_path())
+        .collect()
+}
+
+fn same_size_remote(client: &mut SftpClient, local_path: &Path, remote_path: &Path) -> bool {
+    let local_size = std::fs::metadata(local_path).map(|m| m.len()).ok();
+    let remote_size = client.stat_remote(remote_path).ok().and_then(|s| s.size);
+    local_size.is_some() && local_size == remote_size
+}
+
+/**
+ * The size of a local file for the byte progressbars, 0 if it cannot be read (its upload reports the error)
+ */
+fn local_file_size(path: &Path) -> u64 {
+    std::fs::metadata(path).map(|m| m.len()).unwrap_or(0)
+}
+
+/**
+ * The message of the error followed by the messages of its sources,
+ * e.g. "Cannot open remote file /var/www/app.js: Permission denied"
+ */
+fn error_with_sources(error: &dyn std::error::Error) -> String {
+    let mut message = error.to_string();
+    let mut source = error.source();
+    while let Some(cause) = source {
+        message.push_str(&format!(": {}", cause));
+        source = cause.source();
+    }
+    message
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_protected_rename_source() {
+        let options = UploadOptions {
+            protected_paths: vec!["public/uploads".to_string(), "*.env".to_string()],
+            ..Default::default()
+        };
+
+        assert!(options.is_protected_rename_source(Path::new("public/uploads/logo.png")));
+        assert!(options.is_protected_rename_source(Path::new("public")));
+        assert!(options.is_protected_rename_source(Path::new("prod.env")));
+        assert!(!options.is_protected_rename_source(Path::new("public/app.js")));
+        assert!(!options.is_protected_rename_source(Path::new("dist")));
+    }
+
+    #[test]
+    fn test_summary_lists_failed_files() {
+        let mut counts = UploadCounts {
+            uploaded: 2,
+            bytes: 2048,
+            ..Default::default()
+        };
+        let mut worker_counts = UploadCounts::default();
+        worker_counts.add_failed(Path::new("/src/app.js"), "Permission denied".to_string());
+        counts.add(&worker_counts);
+
+        assert_eq!(
+            counts.summary(Duration::from_secs(2)),
+            "Batch finished: 2 uploaded, 0 skipped unchanged, 1 failed - 2.00 KiB in 2.0s (1.00 KiB/s)\nFailed files:\n  - /src/app.js: Permission denied"
+        );
+    }
+}
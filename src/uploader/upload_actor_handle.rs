@@ -1,4 +1,11 @@
-use super::upload_actor::{AuthMethod, UploadActor, UploadActorMessage};
+use super::{
+    upload_actor::{
+        ConnectionConfig, UploadActor, UploadActorMessage, UploadCounts, UploadOptions,
+    },
+    upload_event::EventWriter,
+    upload_manifest::UploadManifest,
+};
+use crate::sftp::sftp_client::SftpClientError;
 use std::{
     path::PathBuf,
     sync::mpsc::{channel as std_channel, SendError, Sender as StdSender},
@@ -12,15 +19,22 @@ pub struct UploadActorHandle {
 impl UploadActorHandle {
     pub fn new(
         count: u8,
-        host: String,
-        port: u16,
-        username: String,
-        auth_method: AuthMethod,
-    ) -> Self {
+        connection_config: ConnectionConfig,
+        upload_options: UploadOptions,
+        manifest: Option<UploadManifest>,
+        event_writer: Option<EventWriter>,
+    ) -> Result<Self, SftpClientError> {
         let (tx, rx) = std_channel();
 
         // Create the actor and pass the channel receiver (rx)
-        let actor = UploadActor::new(rx, count, host, port, username, auth_method);
+        let actor = UploadActor::new(
+            rx,
+            count,
+            connection_config,
+            upload_options,
+            manifest,
+            event_writer,
+        )?;
 
         // spawn the actor
         let thread = std::thread::Builder::new().name("upload_actor_main".to_string());
@@ -29,7 +43,7 @@ impl UploadActorHandle {
         };
 
         // Create the UploadActorHandle object and store the sender (tx)
-        Self { tx }
+        Ok(Self { tx })
     }
 
     pub fn upload_files(
@@ -46,4 +60,67 @@ impl UploadActorHandle {
         self.tx.send(msg)?;
         Ok(())
     }
+
+    /**
+     * Uploads the files and blocks until the upload actor finished them
+     */
+    pub fn push_files(
+        &mut self,
+        files: Vec<PathBuf>,
+        remote_dir: Option<PathBuf>,
+        local_base_dir: Option<PathBuf>,
+    ) -> Result<UploadCounts, oneshot::RecvError> {
+        let (response_tx, response_rx) = oneshot::channel();
+        let msg = UploadActorMessage::PushFiles {
+            files,
+            remote_dir,
+            local_base_dir,
+            response_tx,
+        };
+
+        // No need for error checking here.
+        // If the actor is dead, the next recv() will also fail.
+        let _ = self.tx.send(msg);
+
+        response_rx.recv()
+    }
+
+    pub fn remove_files(
+        &mut self,
+        files: Vec<PathBuf>,
+        remote_dir: Option<PathBuf>,
+        local_base_dir: Option<PathBuf>,
+    ) -> Result<(), SendError<UploadActorMessage>> {
+        let msg = UploadActorMessage::RemoveFiles {
+            files,
+            remote_dir,
+            local_base_dir,
+        };
+        self.tx.send(msg)?;
+        Ok(())
+    }
+
+    pub fn rename_files(
+        &mut self,
+        renames: Vec<(PathBuf, PathBuf)>,
+        remote_dir: Option<PathBuf>,
+        local_base_dir: Option<PathBuf>,
+    ) -> Result<(), SendError<UploadActorMessage>> {
+        let msg = UploadActorMessage::RenamePaths {
+            renames,
+            remote_dir,
+            local_base_dir,
+        };
+        self.tx.send(msg)?;
+        Ok(())
+    }
+
+    pub fn cleanup_temp_files(
+        &mut self,
+        remote_dir: Option<PathBuf>,
+    ) -> Result<(), SendError<UploadActorMessage>> {
+        let msg = UploadActorMessage::CleanupTempFiles { remote_dir };
+        self.tx.send(msg)?;
+        Ok(())
+    }
 }
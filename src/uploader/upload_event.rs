@@ -0,0 +1,136 @@
+use serde::Serialize;
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+/**
+ * Receives the newline-delimited json events, see --output json
+ */
+pub type EventWriter = Box<dyn Write + Send>;
+
+/**
+ * Where the json events are written to
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub enum EventTarget {
+    /**
+     * The text output is written to stderr in this case
+     */
+    Stdout,
+    /**
+     * The file is truncated on startup
+     */
+    File(PathBuf),
+}
+
+impl EventTarget {
+    pub fn open(&self) -> std::io::Result<EventWriter> {
+        match self {
+            EventTarget::Stdout => Ok(Box::new(std::io::stdout())),
+            EventTarget::File(path) => Ok(Box::new(std::fs::File::create(path)?)),
+        }
+    }
+}
+
+/**
+ * One line of the json output, e.g. `{"event":"file_uploaded","file":"/src/app.js","bytes":1024,"duration_ms":12,...}`.
+ * Paths are the absolute local paths.
+ */
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum UploadEvent {
+    BatchDetected {
+        files: usize,
+        remote_dir: Option<PathBuf>,
+    },
+    FileStarted {
+        connection: String,
+        file: PathBuf,
+    },
+    FileUploaded {
+        connection: String,
+        file: PathBuf,
+        bytes: u64,
+        duration_ms: u64,
+    },
+    FileSkipped {
+        connection: String,
+        file: PathBuf,
+    },
+    FileFailed {
+        connection: String,
+        file: PathBuf,
+        /**
+         * See SftpClientError::kind()
+         */
+        error_kind: String,
+        error: String,
+    },
+    /**
+     * The file failed with a transient error and is uploaded again after retry_in_ms,
+     * a file_failed event follows if all retries failed
+     */
+    FileRetrying {
+        connection: String,
+        file: PathBuf,
+        error_kind: String,
+        error: String,
+        retry_in_ms: u64,
+    },
+    BatchFinished {
+        uploaded: u64,
+        skipped: u64,
+        failed: u64,
+        duration_ms: u64,
+    },
+}
+
+impl UploadEvent {
+    pub fn file_failed(connection: &str, file: &Path, error_kind: &str, error: String) -> Self {
+        UploadEvent::FileFailed {
+            connection: connection.to_string(),
+            file: file.to_path_buf(),
+            error_kind: error_kind.to_string(),
+            error,
+        }
+    }
+
+    /**
+     * Serializes the event as one json line with a timestamp
+     */
+    pub fn to_json_line(&self) -> serde_json::Result<String> {
+        #[derive(Serialize)]
+        struct EventLine<'a> {
+            timestamp: String,
+            #[serde(flatten)]
+            event: &'a UploadEvent,
+        }
+
+        serde_json::to_string(&EventLine {
+            timestamp: chrono::Local::now().to_rfc3339(),
+            event: self,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_json_line() {
+        let event = UploadEvent::FileUploaded {
+            connection: "sftp_1".to_string(),
+            file: PathBuf::from("/src/app.js"),
+            bytes: 1024,
+            duration_ms: 12,
+        };
+        let line: serde_json::Value = serde_json::from_str(&event.to_json_line().unwrap()).unwrap();
+
+        assert_eq!(line["event"], "file_uploaded");
+        assert_eq!(line["file"], "/src/app.js");
+        assert_eq!(line["bytes"], 1024);
+        assert!(line["timestamp"].is_string());
+    }
+}
@@ -0,0 +1,330 @@
+use miette::Diagnostic;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    io::Read,
+    path::{Path, PathBuf},
+};
+use thiserror::Error;
+
+/**
+ * The location of the manifest, relative to the project dir (dir of the config file or cwd)
+ */
+pub const MANIFEST_DIR_NAME: &str = ".dev_uploader";
+pub const MANIFEST_FILE_NAME: &str = "state.json";
+
+/**
+ * Bump when the format of state.json changes, manifests with another version are ignored
+ */
+const MANIFEST_VERSION: u32 = 1;
+
+#[derive(Debug, Error, Diagnostic)]
+pub enum ManifestError {
+    #[error("Cannot read upload manifest {}", .path.display())]
+    Read {
+        path: PathBuf,
+        #[source]
+        io_error: std::io::Error,
+    },
+    #[error("Invalid upload manifest {}", .path.display())]
+    #[diagnostic(help("Delete the file, it is recreated on the next upload."))]
+    Parse {
+        path: PathBuf,
+        #[source]
+        json_error: serde_json::Error,
+    },
+    #[error("Cannot write upload manifest {}", .path.display())]
+    Write {
+        path: PathBuf,
+        #[source]
+        io_error: std::io::Error,
+    },
+}
+
+/**
+ * The content hash and size of a file at the time of its last successful upload
+ */
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    /**
+     * Hex encoded sha256 of the file content
+     */
+    pub hash: String,
+    pub size: u64,
+}
+
+impl ManifestEntry {
+    /**
+     * Hashes the content of a local file
+     */
+    pub fn from_file(path: &Path) -> std::io::Result<ManifestEntry> {
+        let mut file = std::fs::File::open(path)?;
+        let mut hasher = Sha256::new();
+        let mut buffer = vec![0u8; 128 * 1024];
+        let mut size = 0;
+        loop {
+            let read = file.read(&mut buffer)?;
+            if read == 0 {
+                break;
+            }
+            hasher.update(&buffer[..read]);
+            size += read as u64;
+        }
+
+        let hash = hasher
+            .finalize()
+            .iter()
+            .fold(String::new(), |mut hex, byte| {
+                let _ = write!(hex, "{:02x}", byte);
+                hex
+            });
+        Ok(ManifestEntry { hash, size })
+    }
+}
+
+/**
+ * The serialized form of state.json:
+ * target key (`user@host:port:remote_dir`) => relative file path => entry
+ */
+#[derive(Debug, Serialize, Deserialize)]
+struct ManifestState {
+    version: u32,
+    targets: BTreeMap<String, BTreeMap<String, ManifestEntry>>,
+}
+
+impl Default for ManifestState {
+    fn default() -> Self {
+        ManifestState {
+            version: MANIFEST_VERSION,
+            targets: BTreeMap::new(),
+        }
+    }
+}
+
+/**
+ * Remembers what was uploaded successfully, so that files with the same content are not uploaded again,
+ * even if a build tool rewrote them with a new mtime.
+ *
+ * CAUTION: Changes on the remote by other processes are not detected.
+ * Delete the manifest file to force a full upload.
+ */
+#[derive(Debug)]
+pub struct UploadManifest {
+    path: PathBuf,
+    /**
+     * `user@host:port`, all entries of this process are stored for this host
+     */
+    host_id: String,
+    state: ManifestState,
+    /**
+     * true if the state changed since the last save
+     */
+    dirty: bool,
+}
+
+impl UploadManifest {
+    pub fn default_path(project_dir: &Path) -> PathBuf {
+        project_dir.join(MANIFEST_DIR_NAME).join(MANIFEST_FILE_NAME)
+    }
+
+    pub fn empty(path: PathBuf, host_id: &str) -> Self {
+        UploadManifest {
+            path,
+            host_id: host_id.to_string(),
+            state: ManifestState::default(),
+            dirty: false,
+        }
+    }
+
+    /**
+     * Reads the manifest file, a missing file results in an empty manifest
+     */
+    pub fn load(path: &Path, host_id: &str) -> Result<Self, ManifestError> {
+        let mut manifest = UploadManifest::empty(path.to_path_buf(), host_id);
+        if !path.is_file() {
+            return Ok(manifest);
+        }
+
+        let content = std::fs::read_to_string(path).map_err(|e| ManifestError::Read {
+            path: path.to_path_buf(),
+            io_error: e,
+        })?;
+        let state: ManifestState =
+            serde_json::from_str(&content).map_err(|e| ManifestError::Parse {
+                path: path.to_path_buf(),
+                json_error: e,
+            })?;
+        if state.version == MANIFEST_VERSION {
+            manifest.state = state;
+        }
+        Ok(manifest)
+    }
+
+    /**
+     * Writes the manifest (if changed) via a temp file, so that a crash never leaves a half-written manifest
+     */
+    pub fn save(&mut self) -> Result<(), ManifestError> {
+        if !self.dirty {
+            return Ok(());
+        }
+        let to_write_error = |e: std::io::Error| ManifestError::Write {
+            path: self.path.clone(),
+            io_error: e,
+        };
+
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent).map_err(to_write_error)?;
+        }
+        let content = serde_json::to_string_pretty(&self.state)
+            .map_err(|e| to_write_error(std::io::Error::other(e)))?;
+        let temp_path = self.path.with_extension("json.tmp");
+        std::fs::write(&temp_path, content).map_err(to_write_error)?;
+        std::fs::rename(&temp_path, &self.path).map_err(to_write_error)?;
+
+        self.dirty = false;
+        Ok(())
+    }
+
+    fn target_key(&self, remote_dir: Option<&Path>) -> String {
+        let remote_dir = remote_dir.unwrap_or(Path::new("."));
+        format!("{}:{}", self.host_id, remote_dir.display())
+    }
+
+    /**
+     * True if the file was uploaded to this target with the same content before
+     */
+    pub fn is_unchanged(
+        &self,
+        remote_dir: Option<&Path>,
+        relative_path: &Path,
+        entry: &ManifestEntry,
+    ) -> bool {
+        self.state
+            .targets
+            .get(&self.target_key(remote_dir))
+            .and_then(|files| files.get(&relative_key(relative_path)))
+            .map(|known| known == entry)
+            .unwrap_or(false)
+    }
+
+    /**
+     * Stores the entry of a successfully uploaded file
+     */
+    pub fn record(
+        &mut self,
+        remote_dir: Option<&Path>,
+        relative_path: &Path,
+        entry: ManifestEntry,
+    ) {
+        let target_key = self.target_key(remote_dir);
+        self.state
+            .targets
+            .entry(target_key)
+            .or_default()
+            .insert(relative_key(relative_path), entry);
+        self.dirty = true;
+    }
+
+    /**
+     * Forgets a removed file or dir (including all files inside the dir)
+     */
+    pub fn remove(&mut self, remote_dir: Option<&Path>, relative_path: &Path) {
+        let target_key = self.target_key(remote_dir);
+        let Some(files) = self.state.targets.get_mut(&target_key) else {
+            return;
+        };
+
+        let count_before = files.len();
+        files.retain(|file, _| !Path::new(file).starts_with(relative_path));
+        self.dirty |= files.len() != count_before;
+    }
+
+    /**
+     * Moves the entries of a renamed file or dir (including all files inside the dir)
+     */
+    pub fn rename(&mut self, remote_dir: Option<&Path>, from: &Path, to: &Path) {
+        let target_key = self.target_key(remote_dir);
+        let Some(files) = self.state.targets.get_mut(&target_key) else {
+            return;
+        };
+
+        let moved_files = files
+            .keys()
+            .filter(|file| Path::new(file).starts_with(from))
+            .cloned()
+            .collect::<Vec<_>>();
+        for file in moved_files {
+            let entry = files.remove(&file).unwrap();
+            let rest = Path::new(&file).strip_prefix(from).unwrap();
+            files.insert(relative_key(&to.join(rest)), entry);
+            self.dirty = true;
+        }
+    }
+}
+
+/**
+ * Relative paths are stored with '/' on all platforms, so that the manifest can be shared
+ */
+fn relative_key(relative_path: &Path) -> String {
+    relative_path
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(hash: &str) -> ManifestEntry {
+        ManifestEntry {
+            hash: hash.to_string(),
+            size: 1,
+        }
+    }
+
+    #[test]
+    fn test_record_rename_remove() {
+        let mut manifest = UploadManifest::empty(PathBuf::from("state.json"), "me@host:22");
+        let target = Some(Path::new("www"));
+        manifest.record(target, Path::new("assets/a.js"), entry("a"));
+
+        assert!(manifest.is_unchanged(target, Path::new("assets/a.js"), &entry("a")));
+        assert!(!manifest.is_unchanged(target, Path::new("assets/a.js"), &entry("b")));
+        assert!(!manifest.is_unchanged(
+            Some(Path::new("api")),
+            Path::new("assets/a.js"),
+            &entry("a")
+        ));
+
+        manifest.rename(target, Path::new("assets"), Path::new("static"));
+        assert!(manifest.is_unchanged(target, Path::new("static/a.js"), &entry("a")));
+        assert!(!manifest.is_unchanged(target, Path::new("assets/a.js"), &entry("a")));
+
+        manifest.remove(target, Path::new("static"));
+        assert!(!manifest.is_unchanged(target, Path::new("static/a.js"), &entry("a")));
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let temp_dir = tempfile::Builder::new()
+            .prefix("dev_uploader_manifest_test")
+            .tempdir()
+            .unwrap();
+        let dir = temp_dir.path().to_path_buf();
+        let path = UploadManifest::default_path(&dir);
+        let target = Some(Path::new("www"));
+
+        let mut manifest = UploadManifest::empty(path.clone(), "me@host:22");
+        manifest.record(target, Path::new("index.html"), entry("abc"));
+        manifest.save().unwrap();
+
+        let loaded = UploadManifest::load(&path, "me@host:22").unwrap();
+        assert!(loaded.is_unchanged(target, Path::new("index.html"), &entry("abc")));
+        let other_host = UploadManifest::load(&path, "me@other:22").unwrap();
+        assert!(!other_host.is_unchanged(target, Path::new("index.html"), &entry("abc")));
+    }
+}
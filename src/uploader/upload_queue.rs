@@ -0,0 +1,228 @@
+use crate::utils::backoff_delay;
+use std::{
+    collections::{HashMap, VecDeque},
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/**
+ * How often a file which changes during its upload is queued again, before it counts as failed
+ */
+pub const MAX_REQUEUES: u32 = 3;
+
+/**
+ * Retry settings for files which failed with a transient error (see SftpClientError::is_transient()):
+ * retried after 1s, 2s, 4s, ... (max 30s), gives up after MAX_RETRIES retries
+ */
+pub const MAX_RETRIES: u32 = 3;
+const RETRY_BASE_DELAY: Duration = Duration::from_secs(1);
+const RETRY_MAX_DELAY: Duration = Duration::from_secs(30);
+
+/**
+ * In which order the files of a batch are handed out to the upload workers
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum QueueOrder {
+    /**
+     * In the order the files were detected
+     */
+    #[default]
+    Detected,
+    /**
+     * Biggest files first, so that a big file does not start last and keep one connection busy
+     * while all others are idle already
+     */
+    LargestFirst,
+}
+
+/**
+ * A shared queue of files to upload.
+ * All upload workers pull their next file from this queue as soon as they are idle,
+ * so a worker which is stuck on a big file does not block the files behind it.
+ *
+ * Cloning the queue is cheap, all clones share the same files.
+ */
+#[derive(Clone, Default)]
+pub struct UploadQueue {
+    files: Arc<Mutex<VecDeque<PathBuf>>>,
+    /**
+     * How often each file was queued again, see requeue()
+     */
+    requeue_counts: Arc<Mutex<HashMap<PathBuf, u32>>>,
+    /**
+     * Files which failed with a transient error and when they may be retried, see retry()
+     */
+    retries: Arc<Mutex<Vec<(Instant, PathBuf)>>>,
+    retry_counts: Arc<Mutex<HashMap<PathBuf, u32>>>,
+}
+
+impl UploadQueue {
+    pub fn new(mut files: Vec<PathBuf>, order: QueueOrder) -> Self {
+        if order == QueueOrder::LargestFirst {
+            // files which cannot be read are sorted last, their upload will report the error
+            files.sort_by_cached_key(|file| {
+                std::cmp::Reverse(std::fs::metadata(file).map(|m| m.len()).unwrap_or(0))
+            });
+        }
+
+        UploadQueue {
+            files: Arc::new(Mutex::new(files.into())),
+            requeue_counts: Arc::default(),
+            retries: Arc::default(),
+            retry_counts: Arc::default(),
+        }
+    }
+
+    /**
+     * Takes the next file from the queue, returns None when the queue is empty.
+     * Files waiting for a retry are handed out last: when only those are left,
+     * this blocks until the retry which is due first.
+     */
+    pub fn pop(&self) -> Option<PathBuf> {
+        if let Some(file) = self.lock().pop_front() {
+            return Some(file);
+        }
+
+        let (due, file) = {
+            let mut retries = self.retries.lock().unwrap_or_else(|e| e.into_inner());
+            let next_index = (0..retries.len()).min_by_key(|&index| retries[index].0)?;
+            retries.swap_remove(next_index)
+        };
+        std::thread::sleep(due.saturating_duration_since(Instant::now()));
+        Some(file)
+    }
+
+    /**
+     * Appends a file again, e.g. because it changed during its upload.
+     * Returns false (and does not queue the file) if it was queued again MAX_REQUEUES times already.
+     */
+    pub fn requeue(&self, file: PathBuf) -> bool {
+        let mut requeue_counts = self
+            .requeue_counts
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        let count = requeue_counts.entry(file.clone()).or_default();
+        if *count >= MAX_REQUEUES {
+            return false;
+        }
+        *count += 1;
+        self.lock().push_back(file);
+        true
+    }
+
+    /**
+     * Takes all files out of the queue, including the files waiting for a retry (without waiting for them)
+     */
+    pub fn drain(&self) -> Vec<PathBuf> {
+        let mut files: Vec<PathBuf> = self.lock().drain(..).collect();
+        let mut retries = self.retries.lock().unwrap_or_else(|e| e.into_inner());
+        files.extend(retries.drain(..).map(|(_, file)| file));
+        files
+    }
+
+    /**
+     * Queues a file again after a transient error, it is handed out after an exponential backoff.
+     * Returns the backoff, or None (and does not queue the file) if it was retried MAX_RETRIES times already.
+     */
+    pub fn retry(&self, file: PathBuf) -> Option<Duration> {
+        let mut retry_counts = self.retry_counts.lock().unwrap_or_else(|e| e.into_inner());
+        let count = retry_counts.entry(file.clone()).or_default();
+        if *count >= MAX_RETRIES {
+            return None;
+        }
+        let delay = backoff_delay(*count, RETRY_BASE_DELAY, RETRY_MAX_DELAY);
+        *count += 1;
+        self.retries
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push((Instant::now() + delay, file));
+        Some(delay)
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, VecDeque<PathBuf>> {
+        // a panicking worker cannot leave the VecDeque in a broken state, so a poisoned lock is still usable
+        self.files.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_queue_keeps_detected_order() {
+        let queue = UploadQueue::new(
+            vec![PathBuf::from("a"), PathBuf::from("b")],
+            QueueOrder::Detected,
+        );
+        let worker_queue = queue.clone();
+
+        assert_eq!(worker_queue.pop(), Some(PathBuf::from("a")));
+        assert_eq!(queue.pop(), Some(PathBuf::from("b")));
+        assert_eq!(worker_queue.pop(), None);
+    }
+
+    #[test]
+    fn test_requeue_is_limited() {
+        let queue = UploadQueue::new(vec![PathBuf::from("a")], QueueOrder::Detected);
+        let file = queue.pop().unwrap();
+        for _ in 0..MAX_REQUEUES {
+            assert!(queue.requeue(file.clone()));
+            assert_eq!(queue.pop(), Some(file.clone()));
+        }
+        assert!(!queue.requeue(file));
+        assert_eq!(queue.pop(), None);
+    }
+
+    #[test]
+    fn test_retry_waits_for_backoff() {
+        let queue = UploadQueue::new(vec![PathBuf::from("a")], QueueOrder::Detected);
+        let file = queue.pop().unwrap();
+        assert_eq!(queue.retry(file.clone()), Some(Duration::from_secs(1)));
+        assert_eq!(queue.retry(file.clone()), Some(Duration::from_secs(2)));
+        assert_eq!(queue.retry(file.clone()), Some(Duration::from_secs(4)));
+        assert_eq!(queue.retry(file.clone()), None);
+
+        let started = Instant::now();
+        assert_eq!(queue.pop(), Some(file));
+        assert!(started.elapsed() >= Duration::from_millis(900));
+    }
+
+    #[test]
+    fn test_drain_includes_retries() {
+        let queue = UploadQueue::new(
+            vec![PathBuf::from("a"), PathBuf::from("b")],
+            QueueOrder::Detected,
+        );
+        let file = queue.pop().unwrap();
+        queue.retry(file);
+
+        let started = Instant::now();
+        assert_eq!(queue.drain(), vec![PathBuf::from("b"), PathBuf::from("a")]);
+        assert!(started.elapsed() < Duration::from_millis(500));
+        assert_eq!(queue.pop(), None);
+    }
+
+    #[test]
+    fn test_queue_largest_first() {
+        let temp_dir = tempfile::Builder::new()
+            .prefix("dev_uploader_upload_queue_test")
+            .tempdir()
+            .unwrap();
+        let dir = temp_dir.path().to_path_buf();
+        let small = dir.join("small.txt");
+        let big = dir.join("big.txt");
+        std::fs::write(&small, "1").unwrap();
+        std::fs::write(&big, "1234567890").unwrap();
+        let missing = dir.join("missing.txt");
+
+        let queue = UploadQueue::new(
+            vec![missing.clone(), small.clone(), big.clone()],
+            QueueOrder::LargestFirst,
+        );
+        assert_eq!(queue.pop(), Some(big));
+        assert_eq!(queue.pop(), Some(small));
+        assert_eq!(queue.pop(), Some(missing));
+    }
+}
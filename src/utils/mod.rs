@@ -1,20 +1,12 @@
-pub fn split_to_n_chunks<T: Clone>(array: Vec<T>, n: usize) -> Vec<Vec<T>> {
-    if n == 0 {
-        panic!("n must be greater than 0");
-    }
-
-    let mut input = array.clone();
-    let mut result = Vec::new();
-    let mut i = n;
+use std::time::Duration;
 
-    while i > 0 {
-        let chunk_size = (input.len() as f64 / i as f64).ceil() as usize;
-        let chunk: Vec<T> = input.drain(0..chunk_size.min(input.len())).collect();
-        result.push(chunk);
-        i -= 1;
-    }
-
-    result
+/**
+ * Exponential backoff: base_delay * 2^attempt, capped at max_delay.
+ * attempt starts at 0 for the first retry.
+ */
+pub fn backoff_delay(attempt: u32, base_delay: Duration, max_delay: Duration) -> Duration {
+    let factor = 2u32.saturating_pow(attempt);
+    base_delay.saturating_mul(factor).min(max_delay)
 }
 
 #[cfg(test)]
@@ -22,26 +14,14 @@ mod tests {
     use super::*;
 
     #[test]
-    fn test_split_to_n_chunks_exact() {
-        let array = vec![1, 2, 3, 4, 5, 6, 7, 8, 9];
-        let chunks = split_to_n_chunks(array, 3);
-        assert!(chunks.len() == 3);
-        println!("{:?}", chunks);
-    }
-
-    #[test]
-    fn test_split_to_n_chunks_overflow() {
-        let array = vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10];
-        let chunks = split_to_n_chunks(array, 3);
-        assert!(chunks.len() == 3);
-        println!("{:?}", chunks);
-    }
-
-    #[test]
-    fn test_split_to_n_chunks_underflow() {
-        let array = vec![1, 2, 3, 4];
-        let chunks = split_to_n_chunks(array, 6);
-        assert!(chunks.len() == 6);
-        println!("{:?}", chunks);
+    fn test_backoff_delay() {
+        let base = Duration::from_millis(500);
+        let max = Duration::from_secs(30);
+        assert_eq!(backoff_delay(0, base, max), Duration::from_millis(500));
+        assert_eq!(backoff_delay(1, base, max), Duration::from_secs(1));
+        assert_eq!(backoff_delay(3, base, max), Duration::from_secs(4));
+        // capped and no overflow for large attempts
+        assert_eq!(backoff_delay(10, base, max), max);
+        assert_eq!(backoff_delay(100, base, max), max);
     }
 }
@@ -1,2 +1,4 @@
+pub mod path_filter;
+pub mod settle;
 pub mod watch_actor;
 pub mod watch_actor_handle;
@@ -0,0 +1,243 @@
+use ignore_files::IgnoreFile;
+use std::{
+    collections::HashMap,
+    io::{Error, ErrorKind},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use watchexec::filter::Filterer;
+use watchexec_events::{Event, FileType, Priority, Tag};
+use watchexec_filterer_globset::GlobsetFilterer;
+
+/**
+ * The ignore files read with --respect-ignore-files, in the order of their priority (later files win)
+ */
+pub const IGNORE_FILE_NAMES: [&str; 3] = [".gitignore", ".ignore", ".devuploaderignore"];
+
+/**
+ * Decides which files of a watch dir are uploaded.
+ * The same filter is used for the watch events and for the initial walk (--upload-initial and push),
+ * so both always see the same files.
+ *
+ * Combines:
+ * - the substring filters: -i (path includes) and -e (path ends with)
+ * - gitignore-style globs via --include and --exclude, using the GlobsetFilterer of watchexec.
+ *   Globs without a slash match at any depth (like `*.map`), globs with a slash are relative to the watch dir.
+ *   Note: Globs are matched against file paths, so a dir is excluded with a glob for its content (`dir/` + `**`), not with its plain name.
+ * - the ignore files of the watch dir and its sub dirs (see IGNORE_FILE_NAMES), if enabled.
+ *   Unlike the globs above, a rule for a dir also ignores everything inside it, like in git.
+ */
+#[derive(Clone, Default)]
+pub struct PathFilter {
+    ignore_includes: Vec<String>,
+    ignore_ends: Vec<String>,
+    /**
+     * None if no --include or --exclude globs are given and ignore files are not respected
+     */
+    globset_filterer: Option<Arc<GlobsetFilterer>>,
+}
+
+impl PathFilter {
+    /**
+     * Builds the filter for one watch dir.
+     * Must not be called from inside a tokio runtime, since the GlobsetFilterer is created on its own runtime.
+     */
+    pub fn new(
+        watch_dir: &Path,
+        ignore_includes: Vec<String>,
+        ignore_ends: Vec<String>,
+        include_globs: &[String],
+        exclude_globs: &[String],
+        respect_ignore_files: bool,
+    ) -> Result<Self, Error> {
+        let mut path_filter = PathFilter {
+            ignore_includes,
+            ignore_ends,
+            globset_filterer: None,
+        };
+        if include_globs.is_empty() && exclude_globs.is_empty() && !respect_ignore_files {
+            return Ok(path_filter);
+        }
+
+        // the events of the watcher and the walk contain canonicalized paths
+        let origin = watch_dir.canonicalize()?;
+        let to_glob_lines = |globs: &[String]| {
+            globs
+                .iter()
+                .map(|glob| (glob.clone(), None))
+                .collect::<Vec<(String, Option<PathBuf>)>>()
+        };
+
+        let mut exclude_lines = to_glob_lines(exclude_globs);
+        let mut ignore_files = vec![];
+        if respect_ignore_files {
+            // the .git dir is never listed in a .gitignore, but its churn must not trigger uploads
+            exclude_lines.push(("**/.git/**".to_string(), None));
+            ignore_files = find_ignore_files(&origin);
+        }
+
+        let runtime = tokio::runtime::Builder::new_current_thread().build()?;
+        let filterer = runtime
+            .block_on(GlobsetFilterer::new(
+                &origin,
+                to_glob_lines(include_globs),
+                exclude_lines,
+                vec![],
+                ignore_files,
+                vec![],
+            ))
+            .map_err(|e| {
+                Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("Invalid glob or ignore file: {}", e),
+                )
+            })?;
+
+        path_filter.globset_filterer = Some(Arc::new(filterer));
+        Ok(path_filter)
+    }
+
+    /**
+     * True if the (absolute) path passes all filters
+     */
+    pub fn is_allowed(&self, path: &Path) -> bool {
+        // convert to string first, since path.ends_with() only works with full path segments!
+        let path_str = path.to_string_lossy();
+        if self.ignore_ends.iter().any(|e| path_str.ends_with(e))
+            || self.ignore_includes.iter().any(|i| path_str.contains(i))
+        {
+            return false;
+        }
+
+        let Some(filterer) = self.globset_filterer.as_ref() else {
+            return true;
+        };
+
+        // check each path on its own, the filterer passes an event if ANY of its paths passes
+        let file_type = match path.is_dir() {
+            true => FileType::Dir,
+            false => FileType::File,
+        };
+        let event = Event {
+            tags: vec![Tag::Path {
+                path: path.to_path_buf(),
+                file_type: Some(file_type),
+            }],
+            metadata: HashMap::new(),
+        };
+        // the GlobsetFilterer never errors
+        filterer
+            .check_event(&event, Priority::Normal)
+            .unwrap_or(true)
+    }
+}
+
+/**
+ * Finds the ignore files in the watch dir and all of its sub dirs.
+ * Dirs which are ignored by the ignore files of their parents (like node_modules) are not searched.
+ */
+fn find_ignore_files(origin: &Path) -> Vec<IgnoreFile> {
+    let mut walk_builder = ignore::WalkBuilder::new(origin);
+    walk_builder
+        .standard_filters(false)
+        .git_ignore(true)
+        .ignore(true)
+        .add_custom_ignore_filename(IGNORE_FILE_NAMES[2])
+        // read .gitignore files outside of git repos too
+        .require_git(false)
+        .parents(false)
+        .filter_entry(|entry| entry.file_name() != ".git");
+
+    walk_builder
+        .build()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_some_and(|t| t.is_dir()))
+        .flat_map(|dir| {
+            IGNORE_FILE_NAMES
+                .iter()
+                .map(|name| dir.path().join(name))
+                .filter(|path| path.is_file())
+                .map(|path| IgnoreFile {
+                    path,
+                    applies_in: Some(dir.path().to_path_buf()),
+                    applies_to: None,
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_include_and_exclude_globs() {
+        let temp_dir = tempfile::Builder::new()
+            .prefix("dev_uploader_path_filter_test")
+            .tempdir()
+            .unwrap();
+        let dir = temp_dir.path().to_path_buf();
+        std::fs::create_dir_all(dir.join("assets/icons")).unwrap();
+        let dir = dir.canonicalize().unwrap();
+
+        let path_filter = PathFilter::new(
+            &dir,
+            vec![],
+            vec![],
+            &["assets/**/*.{png,svg}".to_string(), "*.js".to_string()],
+            &["**/*.min.js".to_string()],
+            false,
+        )
+        .unwrap();
+
+        assert!(path_filter.is_allowed(&dir.join("assets/icons/logo.svg")));
+        assert!(path_filter.is_allowed(&dir.join("main.js")));
+        assert!(path_filter.is_allowed(&dir.join("lib/vendor.js")));
+        assert!(!path_filter.is_allowed(&dir.join("lib/vendor.min.js")));
+        assert!(!path_filter.is_allowed(&dir.join("icons/logo.svg")));
+        assert!(!path_filter.is_allowed(&dir.join("main.js.map")));
+    }
+
+    #[test]
+    fn test_substring_filters() {
+        let path_filter = PathFilter::new(
+            Path::new("."),
+            vec!["node_modules".to_string()],
+            vec![".map".to_string()],
+            &[],
+            &[],
+            false,
+        )
+        .unwrap();
+
+        assert!(path_filter.is_allowed(Path::new("/watch/main.js")));
+        assert!(!path_filter.is_allowed(Path::new("/watch/main.js.map")));
+        assert!(!path_filter.is_allowed(Path::new("/watch/node_modules/a.js")));
+    }
+
+    #[test]
+    fn test_ignore_files() {
+        let temp_dir = tempfile::Builder::new()
+            .prefix("dev_uploader_ignore_files_test")
+            .tempdir()
+            .unwrap();
+        let dir = temp_dir.path().to_path_buf();
+        std::fs::create_dir_all(dir.join("node_modules/lib")).unwrap();
+        std::fs::create_dir_all(dir.join("src/generated")).unwrap();
+        std::fs::create_dir_all(dir.join(".git")).unwrap();
+        let dir = dir.canonicalize().unwrap();
+        std::fs::write(dir.join(".gitignore"), "node_modules/\n*.log\n").unwrap();
+        std::fs::write(dir.join(".devuploaderignore"), "!keep.log\n").unwrap();
+        std::fs::write(dir.join("src/.ignore"), "generated/\n").unwrap();
+
+        let path_filter = PathFilter::new(&dir, vec![], vec![], &[], &[], true).unwrap();
+
+        assert!(path_filter.is_allowed(&dir.join("src/main.js")));
+        assert!(path_filter.is_allowed(&dir.join("keep.log")));
+        assert!(!path_filter.is_allowed(&dir.join("debug.log")));
+        assert!(!path_filter.is_allowed(&dir.join("node_modules/lib/index.js")));
+        assert!(!path_filter.is_allowed(&dir.join("src/generated/api.js")));
+        assert!(!path_filter.is_allowed(&dir.join(".git/index")));
+    }
+}
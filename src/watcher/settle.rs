@@ -0,0 +1,182 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::mpsc::{Receiver as StdReceiver, RecvTimeoutError, Sender as StdSender},
+    time::{Duration, SystemTime},
+};
+
+use super::watch_actor::{dedupe_file_changes, FileChange};
+
+/**
+ * How often the marker file is checked while a batch is waiting for it
+ */
+const MARKER_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/**
+ * Holds back the batches of the watcher until a build is finished,
+ * so that a build which writes its files over several seconds is uploaded as one batch.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub enum SettleMode {
+    /**
+     * The build is finished when no file changed for this duration
+     */
+    Quiet(Duration),
+    /**
+     * The build is finished when this file is (re-)written, e.g. `.build-complete` written by the last build step.
+     * Relative paths are relative to the watch dir. The marker itself is never uploaded.
+     */
+    Marker(PathBuf),
+}
+
+/**
+ * Merges the batches of the watcher (raw_rx) and forwards them to files_to_upload_tx, once the build settled.
+ * Returns when the watcher is stopped.
+ */
+pub fn settle_batches(
+    raw_rx: StdReceiver<Vec<FileChange>>,
+    files_to_upload_tx: StdSender<Vec<FileChange>>,
+    settle_mode: SettleMode,
+    sync_deletes: bool,
+) {
+    let mut marker_mtime = match &settle_mode {
+        SettleMode::Marker(marker) => read_mtime(marker),
+        SettleMode::Quiet(_) => None,
+    };
+
+    // wait for the first batch of the next build
+    while let Ok(first_batch) = raw_rx.recv() {
+        let mut pending = first_batch;
+        let mut watcher_stopped = false;
+
+        loop {
+            let timeout = match &settle_mode {
+                SettleMode::Quiet(quiet_duration) => *quiet_duration,
+                SettleMode::Marker(marker) => {
+                    let current_mtime = read_mtime(marker);
+                    if current_mtime.is_some() && current_mtime != marker_mtime {
+                        marker_mtime = current_mtime;
+                        break;
+                    }
+                    MARKER_POLL_INTERVAL
+                }
+            };
+
+            match raw_rx.recv_timeout(timeout) {
+                Ok(batch) => pending.extend(batch),
+                Err(RecvTimeoutError::Timeout) => {
+                    if let SettleMode::Quiet(_) = settle_mode {
+                        break;
+                    }
+                }
+                Err(RecvTimeoutError::Disconnected) => {
+                    watcher_stopped = true;
+                    break;
+                }
+            }
+        }
+
+        let mut files_to_upload = dedupe_file_changes(pending.into_iter(), sync_deletes);
+        if let SettleMode::Marker(marker) = &settle_mode {
+            files_to_upload.retain(|change| !is_marker(change.path(), marker));
+        }
+        if !files_to_upload.is_empty() && files_to_upload_tx.send(files_to_upload).is_err() {
+            return;
+        }
+        if watcher_stopped {
+            return;
+        }
+    }
+}
+
+fn read_mtime(path: &Path) -> Option<SystemTime> {
+    path.metadata().and_then(|m| m.modified()).ok()
+}
+
+/**
+ * The paths of the watcher are canonicalized, the marker path may not exist (yet) => compare both forms
+ */
+fn is_marker(path: &Path, marker: &Path) -> bool {
+    path == marker || marker.canonicalize().is_ok_and(|marker| path == marker)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::channel as std_channel;
+
+    #[test]
+    fn test_quiet_mode_merges_batches() {
+        let (raw_tx, raw_rx) = std_channel();
+        let (files_to_upload_tx, files_to_upload_rx) = std_channel();
+        let settle_thread = std::thread::spawn(move || {
+            settle_batches(
+                raw_rx,
+                files_to_upload_tx,
+                SettleMode::Quiet(Duration::from_millis(300)),
+                false,
+            )
+        });
+
+        raw_tx
+            .send(vec![FileChange::Upload(PathBuf::from("/watch/a.js"))])
+            .unwrap();
+        std::thread::sleep(Duration::from_millis(50));
+        raw_tx
+            .send(vec![FileChange::Upload(PathBuf::from("/watch/b.js"))])
+            .unwrap();
+
+        let mut batch = files_to_upload_rx.recv().unwrap();
+        batch.sort_by(|a, b| a.path().cmp(b.path()));
+        assert_eq!(
+            batch,
+            vec![
+                FileChange::Upload(PathBuf::from("/watch/a.js")),
+                FileChange::Upload(PathBuf::from("/watch/b.js")),
+            ]
+        );
+
+        drop(raw_tx);
+        settle_thread.join().unwrap();
+    }
+
+    #[test]
+    fn test_marker_mode_waits_for_marker() {
+        let temp_dir = tempfile::Builder::new()
+            .prefix("dev_uploader_settle_test")
+            .tempdir()
+            .unwrap();
+        let dir = temp_dir.path().to_path_buf();
+        let marker = dir.join(".build-complete");
+
+        let (raw_tx, raw_rx) = std_channel();
+        let (files_to_upload_tx, files_to_upload_rx) = std_channel();
+        let settle_marker = marker.clone();
+        let settle_thread = std::thread::spawn(move || {
+            settle_batches(
+                raw_rx,
+                files_to_upload_tx,
+                SettleMode::Marker(settle_marker),
+                false,
+            )
+        });
+
+        raw_tx
+            .send(vec![FileChange::Upload(dir.join("main.js"))])
+            .unwrap();
+        assert!(files_to_upload_rx
+            .recv_timeout(Duration::from_millis(500))
+            .is_err());
+
+        std::fs::write(&marker, "").unwrap();
+        raw_tx
+            .send(vec![FileChange::Upload(marker.clone())])
+            .unwrap();
+        assert_eq!(
+            files_to_upload_rx.recv().unwrap(),
+            vec![FileChange::Upload(dir.join("main.js"))]
+        );
+
+        drop(raw_tx);
+        settle_thread.join().unwrap();
+    }
+}
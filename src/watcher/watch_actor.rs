@@ -1,4 +1,4 @@
-use std::collections::HashSet;
+use std::collections::HashMap;
 use std::io::{Error, ErrorKind};
 use std::path::PathBuf;
 use std::sync::mpsc::Sender as StdSender;
@@ -7,22 +7,65 @@ use std::time::Duration;
 use miette::IntoDiagnostic;
 use tokio::io::Result as TokioResult;
 use watchexec::Watchexec;
-use watchexec_events::filekind::{FileEventKind, ModifyKind};
+use watchexec_events::filekind::{FileEventKind, ModifyKind, RenameMode};
 use watchexec_events::Tag;
 use watchexec_signals::Signal;
 
+use super::path_filter::PathFilter;
+
+/**
+ * One change of a file inside the watch dir, which should be applied to the remote
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub enum FileChange {
+    /**
+     * The file was created or modified => upload it
+     */
+    Upload(PathBuf),
+    /**
+     * The file (or an empty dir) was removed => remove it on the remote (only with --sync-deletes)
+     */
+    Remove(PathBuf),
+    /**
+     * A file or dir was renamed inside the watch dir => rename it on the remote
+     */
+    Rename { from: PathBuf, to: PathBuf },
+}
+
+impl FileChange {
+    /**
+     * The path of the file after the change
+     */
+    pub fn path(&self) -> &PathBuf {
+        match self {
+            FileChange::Upload(path) | FileChange::Remove(path) => path,
+            FileChange::Rename { to, .. } => to,
+        }
+    }
+}
+
 /**
  * A watch actor: It will watch a directory for changes and send the paths of changed files to the outside world
  */
 pub struct WatchActor {
     // Inner state for the actor:
     pub watch_dir: PathBuf,
-    pub ignore_includes: Vec<String>,
-    pub ignore_ends: Vec<String>,
     /**
-     * The watch_event_tx is a Sender which will be used to send the paths of changed files to the outside world
+     * The same filter is used for the initial upload, see watch_actor_handle::collect_files()
+     */
+    pub path_filter: PathFilter,
+    /**
+     * If true, removed files are reported as FileChange::Remove, otherwise removals are ignored
+     */
+    pub sync_deletes: bool,
+    /**
+     * Events are collected for this duration and then sent as one batch
+     */
+    pub debounce: Duration,
+    /**
+     * The watch_event_tx is a Sender which will be used to send the changed files to the outside world
      */
-    pub files_to_upload_tx: StdSender<Vec<PathBuf>>,
+    pub files_to_upload_tx: StdSender<Vec<FileChange>>,
 }
 
 impl WatchActor {
@@ -47,8 +90,9 @@ impl WatchActor {
     pub async fn watch(&mut self) -> TokioResult<()> {
         let files_to_upload_tx = self.files_to_upload_tx.clone();
         let watch_dir = self.watch_dir.clone();
-        let ignore_includes = self.ignore_includes.clone();
-        let ignore_ends = self.ignore_ends.clone();
+        let path_filter = self.path_filter.clone();
+        let sync_deletes = self.sync_deletes;
+        let debounce = self.debounce;
 
         let wx = Watchexec::new(move |mut action| {
             // Debug print
@@ -69,14 +113,12 @@ impl WatchActor {
                 // eprintln!("EVENT: {event:?}\n");
 
                 // Iterate over the tags of an event to decide if it should be filtered or not
-                let some_event_or_none =
-                    match_event_by_tags(&event.tags, &ignore_includes, &ignore_ends);
-                some_event_or_none
+                match_event_by_tags(&event.tags, &path_filter, sync_deletes)
             });
 
-            let files_to_upload = HashSet::<PathBuf>::from_iter(events_iter.cloned());
+            let files_to_upload = dedupe_file_changes(events_iter, sync_deletes);
 
-            match files_to_upload_tx.send(files_to_upload.into_iter().collect()) {
+            match files_to_upload_tx.send(files_to_upload) {
                 Ok(_) => (),
                 Err(e) => eprintln!("Error sending files to upload: {:?}", e),
             }
@@ -97,7 +139,7 @@ impl WatchActor {
 
         // watch the path sent to this function
         ensure_wx.config.pathset([watch_dir]);
-        ensure_wx.config.throttle(Duration::from_millis(1500));
+        ensure_wx.config.throttle(debounce);
 
         match ensure_wx.main().await.into_diagnostic() {
             Ok(_) => return Ok(()),
@@ -113,12 +155,15 @@ impl WatchActor {
 }
 
 // match tags as per event types: https://docs.rs/watchexec-events/latest/watchexec_events/
-fn match_event_by_tags<'a>(
-    tags: &'a Vec<Tag>,
-    ignore_includes: &'a Vec<String>,
-    ignore_ends: &'a Vec<String>,
-) -> Option<&'a PathBuf> {
-    let mut result_path = None;
+fn match_event_by_tags(
+    tags: &Vec<Tag>,
+    path_filter: &PathFilter,
+    sync_deletes: bool,
+) -> Option<FileChange> {
+    let mut result_paths = vec![];
+    let mut is_remove = false;
+    // a rename with both paths (old and new) in one event, only reported by some platforms (e.g. inotify on linux)
+    let mut is_rename_pair = false;
 
     for tag in tags {
         match tag {
@@ -128,26 +173,18 @@ fn match_event_by_tags<'a>(
 
                 // Step 1: ignore directories
                 // They will be implicitly handled by the sftp uploader, like git is doing it
-                if path.is_dir() {
+                // Exception: renamed dirs are renamed on the remote as well
+                // (Note: the FileEventKind tag comes before the Path tags)
+                if path.is_dir() && !is_rename_pair {
                     return None;
                 }
 
-                // Step 2: ignore files based on how their paths ends
-                for pattern in ignore_ends {
-                    // convert to string first, since path.ends_with() only works with full path segments!
-                    if path.to_str().unwrap().ends_with(pattern) {
-                        return None;
-                    }
-                }
-
-                // Step 3: ignore files based on how their paths include a certain string
-                for pattern in ignore_includes {
-                    if path.to_str().unwrap().contains(pattern) {
-                        return None;
-                    }
+                // Step 2: ignore files based on -i, -e, --include and --exclude
+                if !path_filter.is_allowed(path) {
+                    return None;
                 }
 
-                result_path = Some(path);
+                result_paths.push(path);
             }
             Tag::FileEventKind(kind) => {
                 // println!("    File Event Kind: {:?}", kind);
@@ -177,8 +214,9 @@ fn match_event_by_tags<'a>(
                                 // println!("  => Removed in filter, Reason: is Modify Event for Metadata ");
                                 return None;
                             }
-                            ModifyKind::Name(_rename_mode) => {
+                            ModifyKind::Name(rename_mode) => {
                                 // println!("    File or folder name changed.");
+                                is_rename_pair = *rename_mode == RenameMode::Both;
                             }
                             ModifyKind::Other => {
                                 // println!("    It's a different kind of modification event.");
@@ -187,7 +225,10 @@ fn match_event_by_tags<'a>(
                     }
                     FileEventKind::Remove(_remove_kind) => {
                         // println!("File Event: Remove, Kind: {:?}", remove_kind);
-                        return None;
+                        if !sync_deletes {
+                            return None;
+                        }
+                        is_remove = true;
                     }
                     FileEventKind::Other => {
                         // println!("File Event: Other");
@@ -220,5 +261,105 @@ fn match_event_by_tags<'a>(
         }
     }
 
-    return result_path;
+    if let (true, [from, to]) = (is_rename_pair, result_paths.as_slice()) {
+        return Some(FileChange::Rename {
+            from: from.to_path_buf(),
+            to: to.to_path_buf(),
+        });
+    }
+
+    result_paths.last().map(|path| match is_remove {
+        true => FileChange::Remove(path.to_path_buf()),
+        false => FileChange::Upload(path.to_path_buf()),
+    })
+}
+
+/**
+ * Reduces all changes of a batch to one change per path.
+ * The events of a batch may contain create AND remove events for the same path (e.g. editors which save via delete + create),
+ * so the decision is made based on whether the file exists now.
+ *
+ * Renames are reported as separate events for the old and the new path as well (besides the rename pair),
+ * so these events are dropped in favor of the rename.
+ */
+pub(super) fn dedupe_file_changes(
+    changes: impl Iterator<Item = FileChange>,
+    sync_deletes: bool,
+) -> Vec<FileChange> {
+    let mut renames = vec![];
+    let mut deduped: HashMap<PathBuf, FileChange> = HashMap::new();
+    for change in changes {
+        match change {
+            FileChange::Rename { .. } => renames.push(change),
+            change => {
+                deduped.insert(change.path().clone(), change);
+            }
+        }
+    }
+
+    for rename in renames.iter() {
+        if let FileChange::Rename { from, to } = rename {
+            deduped.remove(from);
+            deduped.remove(to);
+        }
+    }
+
+    deduped
+        .into_values()
+        .map(|change| match change {
+            FileChange::Remove(path) if path.is_file() => FileChange::Upload(path),
+            FileChange::Upload(path) if sync_deletes && !path.exists() => FileChange::Remove(path),
+            change => change,
+        })
+        .chain(renames)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dedupe_file_changes_uses_current_file_state() {
+        let temp_dir = tempfile::Builder::new()
+            .prefix("dev_uploader_watch_actor_test")
+            .tempdir()
+            .unwrap();
+        let dir = temp_dir.path().to_path_buf();
+        let recreated = dir.join("recreated.js");
+        std::fs::write(&recreated, "new content").unwrap();
+        let deleted = dir.join("deleted.js");
+
+        let changes = vec![
+            FileChange::Upload(recreated.clone()),
+            FileChange::Remove(recreated.clone()),
+            FileChange::Upload(deleted.clone()),
+        ];
+        let mut deduped = dedupe_file_changes(changes.into_iter(), true);
+        deduped.sort_by(|a, b| a.path().cmp(b.path()));
+
+        assert_eq!(
+            deduped,
+            vec![FileChange::Remove(deleted), FileChange::Upload(recreated)]
+        );
+    }
+
+    #[test]
+    fn test_dedupe_file_changes_prefers_renames() {
+        let from = PathBuf::from("/watch/old_name.js");
+        let to = PathBuf::from("/watch/new_name.js");
+        let changes = vec![
+            FileChange::Upload(from.clone()),
+            FileChange::Upload(to.clone()),
+            FileChange::Rename {
+                from: from.clone(),
+                to: to.clone(),
+            },
+        ];
+
+        assert_eq!(
+            dedupe_file_changes(changes.into_iter(), false),
+            vec![FileChange::Rename { from, to }]
+        );
+    }
 }
@@ -1,50 +1,62 @@
-use super::watch_actor::WatchActor;
+use super::{
+    path_filter::PathFilter,
+    settle::{settle_batches, SettleMode},
+    watch_actor::{FileChange, WatchActor},
+};
 use std::{
-    path::PathBuf,
+    path::{Path, PathBuf},
     sync::mpsc::{channel as std_channel, Receiver as StdReceiver},
+    time::Duration,
 };
 
 pub fn start_watching(
     watch_dir: PathBuf,
     upload_initial: bool,
-    ignore_includes: Vec<String>,
-    ignore_ends: Vec<String>,
-) -> Result<StdReceiver<Vec<PathBuf>>, std::io::Error> {
+    path_filter: PathFilter,
+    sync_deletes: bool,
+    debounce: Duration,
+    settle_mode: Option<SettleMode>,
+) -> Result<StdReceiver<Vec<FileChange>>, std::io::Error> {
     let (files_to_upload_tx, files_to_upload_rx) = std_channel();
 
     // Before creating the watch actor, read the initial files in the directory and send them to the outside world
     if upload_initial {
-        // Get all files in the directory (recursively) as Vec<PathBuf>, without dir paths
-        let files = walkdir::WalkDir::new(&watch_dir)
+        let files = collect_files(&watch_dir, &path_filter)
             .into_iter()
-            .filter_map(|e| e.ok())
-            .filter(|e| e.file_type().is_file())
-            .filter(|e| {
-                let path = e.path();
-                let path_str = path.to_string_lossy();
-
-                // iterate through all patterns in ignore_includes and ignore_ends
-                // and check if path contains or ends with any of them
-                ignore_includes.iter().all(|i| !path_str.contains(i))
-                    && ignore_ends.iter().all(|e| !path_str.ends_with(e))
-            })
-            // bjesuiter: all paths from watch_actor are expected to be absolute, therefore they are canonicalized here
-            .filter_map(|e| match e.path().canonicalize() {
-                Ok(path) => Some(path),
-                Err(_) => {println!("Failed to canonicalize path while collecting paths for --initial-upload: {:?}", e.path()); None},
-            })
-            .collect::<Vec<PathBuf>>();
+            .map(FileChange::Upload)
+            .collect::<Vec<FileChange>>();
 
         // Send the files to the outside world
         files_to_upload_tx.send(files).unwrap();
     }
 
+    // With a settle mode, the batches of the watcher are held back by a settle thread until the build is finished
+    let watcher_tx = match settle_mode {
+        None => files_to_upload_tx,
+        Some(settle_mode) => {
+            // the marker is relative to the watch dir, the watcher reports canonicalized paths
+            let settle_mode = match settle_mode {
+                SettleMode::Marker(marker) => {
+                    SettleMode::Marker(watch_dir.canonicalize()?.join(marker))
+                }
+                settle_mode => settle_mode,
+            };
+            let (raw_tx, raw_rx) = std_channel();
+            let thread = std::thread::Builder::new().name("watch_settle".to_string());
+            thread.spawn(move || {
+                settle_batches(raw_rx, files_to_upload_tx, settle_mode, sync_deletes)
+            })?;
+            raw_tx
+        }
+    };
+
     // Create the WatchActor instance
     let actor = WatchActor {
         watch_dir,
-        ignore_includes,
-        ignore_ends,
-        files_to_upload_tx,
+        path_filter,
+        sync_deletes,
+        debounce,
+        files_to_upload_tx: watcher_tx,
     };
 
     // Spawn the actor thread!
@@ -56,3 +68,28 @@ pub fn start_watching(
     // return the files channel receiver to be able to listen to the "files-changed' events emitted by the watcher
     Ok(files_to_upload_rx)
 }
+
+/**
+ * Returns all files in the directory (recursively) as absolute paths, without dir paths.
+ * Used for --upload-initial and the push subcommand.
+ */
+pub fn collect_files(watch_dir: &Path, path_filter: &PathFilter) -> Vec<PathBuf> {
+    walkdir::WalkDir::new(watch_dir)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file())
+        // bjesuiter: all paths from watch_actor are expected to be absolute, therefore they are canonicalized here
+        .filter_map(|e| match e.path().canonicalize() {
+            Ok(path) => Some(path),
+            Err(_) => {
+                println!(
+                    "Failed to canonicalize path while collecting paths for --initial-upload: {:?}",
+                    e.path()
+                );
+                None
+            }
+        })
+        // the same filter as for the watch events (on the canonicalized path, like the watcher paths)
+        .filter(|path| path_filter.is_allowed(path))
+        .collect()
+}